@@ -0,0 +1,163 @@
+// Demo mode player: picks a placement for each piece by how tidy it leaves the stack, then
+// steers the piece there with the same input a player would give
+use crate::game::{Action, GameState, Input, Tetromino};
+
+// Weights for judging the stack a placement leaves behind
+const HEIGHT_WEIGHT: f32 = -0.51;    // Sum of the column heights
+const LINES_WEIGHT: f32 = 0.76;      // Lines the placement clears
+const HOLES_WEIGHT: f32 = -0.36;     // Empty cells with a filled cell somewhere above them
+const BUMPINESS_WEIGHT: f32 = -0.18; // Sum of the height differences between neighbouring columns
+
+const ACTION_STEPS: u32 = 6;    // Updates between inputs, so the demo plays at a watchable pace
+const GIVE_UP_STEPS: u32 = 240; // Hard drop wherever the piece is if steering it gets stuck this long
+const RESTART_STEPS: u32 = 360; // Updates the game over screen stays up before a new game
+
+// Where the current piece should end up
+struct Plan {
+    piece: u32,    // pieces_placed when the plan was made, identifying the piece it is for
+    rotation: u8,  // Orientation to turn the piece to
+    left: i32,     // Column of the piece's leftmost block once placed
+    steps: u32,    // Updates spent carrying the plan out so far
+}
+
+// Plays the game one update at a time
+#[derive(Default)]
+pub struct Demo {
+    plan: Option<Plan>,
+    game_over_steps: u32, // Updates since the game ended
+}
+
+impl Demo {
+    // The input for the next update: turn the piece to the planned orientation, steer it to
+    // the planned column, then hard drop it
+    pub fn input(&mut self, game: &GameState) -> Input {
+        let mut input = Input::default();
+        if game.game_over {
+            self.plan = None;
+            self.game_over_steps += 1;
+            if self.game_over_steps >= RESTART_STEPS {
+                self.game_over_steps = 0;
+                input.pressed.insert(Action::Restart);
+            }
+            return input;
+        }
+        // The piece on show while a lock flashes or rows clear is already part of the stack,
+        // and the next one waits for the rows above a clear to land
+        if game.is_lock_flashing() || game.is_clearing() || game.is_collapsing() {
+            return input;
+        }
+
+        if self.plan.as_ref().is_none_or(|plan| plan.piece != game.pieces_placed) {
+            self.plan = best_placement(game).map(|(rotation, left)| Plan {
+                piece: game.pieces_placed,
+                rotation,
+                left,
+                steps: 0,
+            });
+        }
+        let Some(plan) = &mut self.plan else {
+            // Nowhere fits, so the game is about to end anyway
+            input.pressed.insert(Action::HardDrop);
+            return input;
+        };
+        plan.steps += 1;
+        if plan.steps % ACTION_STEPS != 0 {
+            return input;
+        }
+
+        let piece = &game.tetromino;
+        let (left, right) = columns(piece);
+        let stuck = plan.steps >= GIVE_UP_STEPS;
+        if piece.rotation() != plan.rotation && !stuck {
+            input.pressed.insert(Action::RotateCw);
+        } else if left != plan.left && !stuck {
+            // Steering aims the middle of the piece at the target column
+            input.target_column = Some(plan.left + (right - left) / 2);
+        } else {
+            input.pressed.insert(Action::HardDrop);
+        }
+        input
+    }
+}
+
+// The leftmost and rightmost columns a piece covers
+fn columns(piece: &Tetromino) -> (i32, i32) {
+    let (left, _, right, _) = piece.bounding_box();
+    (left, right)
+}
+
+// The orientation and leftmost column of the best place to drop the current piece, trying
+// every distinct orientation in every column it fits
+fn best_placement(game: &GameState) -> Option<(u8, i32)> {
+    let filled: Vec<Vec<bool>> = (0..game.height).map(|y| (0..game.width).map(|x| game.cell(x, y).is_some()).collect()).collect();
+    let mut best: Option<(f32, u8, i32)> = None;
+    let mut shapes_tried = Vec::new();
+    let mut piece = game.tetromino.clone();
+    for _ in 0..4 {
+        let (left, top, right, _) = piece.bounding_box();
+        let mut shape: Vec<(i32, i32)> = piece.cells().map(|(x, y)| (x - left, y - top)).collect();
+        shape.sort_unstable();
+        // Turns that give a shape already tried, such as any turn of the O piece, add nothing
+        if !shapes_tried.contains(&shape) {
+            shapes_tried.push(shape);
+            for target in 0..game.width - (right - left) {
+                let cells: Vec<(i32, i32)> = piece.translated(target - left, 0).cells().collect();
+                if !fits(&filled, &cells, 0) {
+                    continue;
+                }
+                let mut dy = 0;
+                while fits(&filled, &cells, dy + 1) {
+                    dy += 1;
+                }
+                let landed: Vec<(i32, i32)> = cells.iter().map(|&(x, y)| (x, y + dy)).collect();
+                let score = evaluate(&filled, &landed);
+                if best.is_none_or(|(best_score, _, _)| score > best_score) {
+                    best = Some((score, piece.rotation(), target));
+                }
+            }
+        }
+        piece = piece.rotated(true);
+    }
+    best.map(|(_, rotation, left)| (rotation, left))
+}
+
+// Whether cells shifted down by dy are inside the grid and clear of the stack; cells above
+// the top are allowed, as for a spawning piece
+fn fits(filled: &[Vec<bool>], cells: &[(i32, i32)], dy: i32) -> bool {
+    let width = filled.first().map_or(0, Vec::len) as i32;
+    cells.iter().all(|&(x, y)| {
+        let y = y + dy;
+        (0..width).contains(&x) && y < filled.len() as i32 && (y < 0 || !filled[y as usize][x as usize])
+    })
+}
+
+// Score the stack left after placing a piece's cells; higher is better
+fn evaluate(filled: &[Vec<bool>], cells: &[(i32, i32)]) -> f32 {
+    let mut grid = filled.to_vec();
+    for &(x, y) in cells {
+        if y < 0 {
+            // Locking above the top ends the game
+            return f32::MIN;
+        }
+        grid[y as usize][x as usize] = true;
+    }
+    let rows = grid.len();
+    grid.retain(|row| !row.iter().all(|&cell| cell));
+    let lines = rows - grid.len();
+
+    let width = grid.first().map_or(0, Vec::len);
+    let mut heights = Vec::with_capacity(width);
+    let mut holes = 0;
+    for x in 0..width {
+        let top = grid.iter().position(|row| row[x]).unwrap_or(grid.len());
+        heights.push(grid.len() - top);
+        holes += grid[top..].iter().filter(|row| !row[x]).count();
+    }
+    let height: usize = heights.iter().sum();
+    let bumpiness: usize = heights.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum();
+
+    HEIGHT_WEIGHT * height as f32
+        + LINES_WEIGHT * lines as f32
+        + HOLES_WEIGHT * holes as f32
+        + BUMPINESS_WEIGHT * bumpiness as f32
+}
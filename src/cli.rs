@@ -0,0 +1,153 @@
+// Command-line argument parsing
+use crate::game::{GameConfig, Theme};
+use crate::pieces;
+
+const MIN_GRID_WIDTH: i32 = 4;
+const MIN_GRID_HEIGHT: i32 = 4;
+const MAX_GRID_SIZE: i32 = 100;
+const DEFAULT_PREVIEW_WIDTH: i32 = 5;
+const MIN_PREVIEW_WIDTH: i32 = 4; // Wide enough for a flat I piece
+const MAX_PREVIEW_WIDTH: i32 = 20;
+const DEFAULT_BLOCK_SIZE: i32 = 25;
+const MIN_BLOCK_SIZE: i32 = 10;
+const MAX_BLOCK_SIZE: i32 = 100;
+const DEFAULT_TARGET_LEVEL: i32 = 15; // Marathon target when --marathon is given no level
+const MAX_TARGET_LEVEL: i32 = 99;
+const MAX_HIDDEN_ROWS: i32 = 4;
+
+// Everything chosen on the command line: the game itself plus frontend layout
+pub struct Options {
+    pub game: GameConfig,
+    pub preview_width: i32, // Side panel width in cells
+    pub block_size: i32,    // Side of one grid cell in pixels
+    pub replay: Option<String>, // Replay file to play back instead of starting at the menu
+    pub demo: bool,         // The computer plays, starting a new game after each one ends
+    pub debug: bool,        // Debug keys, such as the game clock speed, are enabled
+}
+
+// Build the options from the command-line arguments (without the program name)
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut args = args.peekable();
+    let mut config = GameConfig::default();
+    let mut preview_width = DEFAULT_PREVIEW_WIDTH;
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut replay = None;
+    let mut demo = false;
+    let mut debug = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" => config.width = parse_number("--width", args.next(), MIN_GRID_WIDTH, MAX_GRID_SIZE)?,
+            "--height" => config.height = parse_number("--height", args.next(), MIN_GRID_HEIGHT, MAX_GRID_SIZE)?,
+            "--theme" => config.theme = parse_theme(args.next())?,
+            "--lines" => config.target_lines = Some(parse_target_lines(args.next())?),
+            "--marathon" => {
+                // The level is optional, so only take the next argument if it isn't a flag
+                let level = args.next_if(|value| !value.starts_with("--"));
+                config.target_level = Some(match level {
+                    Some(level) => parse_number("--marathon", Some(level), 2, MAX_TARGET_LEVEL)?,
+                    None => DEFAULT_TARGET_LEVEL,
+                });
+            }
+            "--preview-width" => {
+                preview_width = parse_number("--preview-width", args.next(), MIN_PREVIEW_WIDTH, MAX_PREVIEW_WIDTH)?
+            }
+            "--pieces" => config.pieces = Some(pieces::load(&args.next().ok_or("--pieces requires a file")?)?),
+            "--seed" => config.seed = Some(parse_seed(args.next())?),
+            "--replay" => replay = Some(args.next().ok_or("--replay requires a file")?),
+            "--demo" => demo = true,
+            "--debug" => debug = true,
+            "--zen" => config.zen = true,
+            "--practice" => config.practice = true,
+            "--20g" => config.twenty_g = true,
+            "--puzzle" => config.puzzle = true,
+            "--hidden-rows" => config.hidden_rows = parse_number("--hidden-rows", args.next(), 0, MAX_HIDDEN_ROWS)?,
+            "--spawn-offset" => config.spawn_offset = parse_offset(args.next())?,
+            "--garbage" => config.garbage_rows = parse_number("--garbage", args.next(), 0, MAX_GRID_SIZE)?,
+            "--block-size" => {
+                block_size = parse_number("--block-size", args.next(), MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)?
+            }
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+
+    // Leave room above the garbage for pieces to spawn and move
+    if config.garbage_rows > config.height - 4 {
+        return Err(format!(
+            "--garbage must leave at least 4 empty rows, so at most {} for a height of {}",
+            config.height - 4,
+            config.height
+        ));
+    }
+
+    // Every piece must be able to spawn on the grid
+    if let Some(piece) = config.pieces.iter().flatten().find(|piece| piece.width() > config.width) {
+        return Err(format!("piece '{}' is wider than the grid", piece.name));
+    }
+    let set = config.pieces.clone().unwrap_or_else(|| pieces::standard(config.theme));
+    let off_grid = set.iter().find(|piece| {
+        let left = config.width / 2 - (piece.width() + 1) / 2 + piece.spawn.0 + config.spawn_offset.0;
+        left < 0 || left + piece.width() > config.width
+    });
+    if let Some(piece) = off_grid {
+        return Err(format!("piece '{}' would spawn off the side of the grid", piece.name));
+    }
+    if config.spawn_offset.1.abs() > MAX_HIDDEN_ROWS {
+        return Err(format!("--spawn-offset may move pieces at most {} rows up or down", MAX_HIDDEN_ROWS));
+    }
+
+    if demo && replay.is_some() {
+        return Err("--demo and --replay can't be used together".to_string());
+    }
+
+    Ok(Options { game: config, preview_width, block_size, replay, demo, debug })
+}
+
+// Parse a whole-number option, rejecting values outside min..=max
+fn parse_number(flag: &str, value: Option<String>, min: i32, max: i32) -> Result<i32, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    let size: i32 = value
+        .parse()
+        .map_err(|_| format!("{} expects a whole number, got '{}'", flag, value))?;
+    if !(min..=max).contains(&size) {
+        return Err(format!("{} must be between {} and {}, got {}", flag, min, max, size));
+    }
+    Ok(size)
+}
+
+// Parse the sprint mode line target, which must be positive
+fn parse_target_lines(value: Option<String>) -> Result<i32, String> {
+    let value = value.ok_or_else(|| "--lines requires a value".to_string())?;
+    match value.parse() {
+        Ok(lines) if lines > 0 => Ok(lines),
+        _ => Err(format!("--lines expects a positive whole number, got '{}'", value)),
+    }
+}
+
+// Parse a spawn offset written as DX,DY
+fn parse_offset(value: Option<String>) -> Result<(i32, i32), String> {
+    let value = value.ok_or("--spawn-offset requires a value")?;
+    value
+        .split_once(',')
+        .and_then(|(dx, dy)| Some((dx.parse().ok()?, dy.parse().ok()?)))
+        .ok_or_else(|| format!("--spawn-offset expects DX,DY such as 0,-1, got '{}'", value))
+}
+
+// Parse a piece seed
+fn parse_seed(value: Option<String>) -> Result<u64, String> {
+    let value = value.ok_or("--seed requires a value")?;
+    value
+        .parse()
+        .map_err(|_| format!("--seed expects a non-negative whole number, got '{}'", value))
+}
+
+// Parse a color theme name
+fn parse_theme(value: Option<String>) -> Result<Theme, String> {
+    match value.as_deref() {
+        Some("classic") => Ok(Theme::Classic),
+        Some("pastel") => Ok(Theme::Pastel),
+        Some("grayscale") => Ok(Theme::Grayscale),
+        Some(other) => Err(format!("--theme must be classic, pastel or grayscale, got '{}'", other)),
+        None => Err("--theme requires a value".to_string()),
+    }
+}
@@ -0,0 +1,248 @@
+// The tetris.cfg config file: key bindings and settings as `name = value` lines
+use std::fs;
+use std::time::Duration;
+
+use crate::game::{classic_gravity, GameConfig, SoftDropLock, DROP_INTERVAL, LOCK_RESETS, SOFT_DROP_SPEED};
+use crate::keys::KeyBindings;
+use crate::sound::MUSIC_VOLUME;
+
+pub const CONFIG_FILE: &str = "tetris.cfg"; // Config file in the working directory
+pub const MIN_START_LEVEL: i32 = 1;
+pub const MAX_START_LEVEL: i32 = 15;
+pub const MIN_DROP_SETTING: Duration = Duration::from_millis(100); // Fastest selectable level 1 drop speed
+pub const MAX_DROP_SETTING: Duration = Duration::from_millis(1000); // Slowest selectable level 1 drop speed
+pub const MIN_SOFT_DROP_SPEED: u32 = 5; // Soft drop speed limits, in cells per second
+pub const MAX_SOFT_DROP_SPEED: u32 = 60;
+pub const MAX_LOCK_RESETS: u32 = 30; // Most lock delay restarts a piece may be allowed
+pub const MAX_MUSIC_VOLUME: u32 = 100; // Loudest music level, in percent of the effects
+const MAX_GRAVITY_ENTRY: u64 = 10_000; // Slowest drop interval a gravity table may list, in ms
+const BEGINNER_TOP_LEVEL: usize = 10; // Beginner games stop speeding up past this level
+
+// Bundles of the timing options picked on the menu; Custom plays with the values from the
+// config file and settings overlay as they are
+#[derive(Clone, Copy, PartialEq)]
+pub enum Difficulty {
+    Beginner,
+    Standard,
+    Expert,
+    Custom,
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 4] = [Difficulty::Beginner, Difficulty::Standard, Difficulty::Expert, Difficulty::Custom];
+
+    // The name used in the config file and on the menu
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "beginner",
+            Difficulty::Standard => "standard",
+            Difficulty::Expert => "expert",
+            Difficulty::Custom => "custom",
+        }
+    }
+
+    // The name shown on the menu
+    pub fn label(self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "Beginner",
+            Difficulty::Standard => "Standard",
+            Difficulty::Expert => "Expert",
+            Difficulty::Custom => "Custom",
+        }
+    }
+
+    // The next preset in menu order, wrapping around, or the previous one going backwards
+    pub fn cycle(self, forward: bool) -> Difficulty {
+        let index = Self::ALL.iter().position(|&preset| preset == self).unwrap_or(0);
+        let step = if forward { 1 } else { Self::ALL.len() - 1 };
+        Self::ALL[(index + step) % Self::ALL.len()]
+    }
+
+    // Set the preset's start level, drop speeds, lock delay and auto-repeat on a game's options
+    pub fn apply(self, config: &mut GameConfig) {
+        let standard = GameConfig::default();
+        let (start_level, drop_interval, lock_delay, das_delay, arr_rate) = match self {
+            Difficulty::Beginner => (1, 1000, 1000, 220, 70),
+            Difficulty::Standard => (
+                standard.start_level,
+                standard.drop_interval.as_millis() as u64,
+                standard.lock_delay.as_millis() as u64,
+                standard.das_delay.as_millis() as u64,
+                standard.arr_rate.as_millis() as u64,
+            ),
+            Difficulty::Expert => (8, 400, 300, 120, 25),
+            Difficulty::Custom => return,
+        };
+        config.start_level = start_level;
+        config.drop_interval = Duration::from_millis(drop_interval);
+        config.lock_delay = Duration::from_millis(lock_delay);
+        config.das_delay = Duration::from_millis(das_delay);
+        config.arr_rate = Duration::from_millis(arr_rate);
+        config.gravity = classic_gravity();
+        if self == Difficulty::Beginner {
+            config.gravity.truncate(BEGINNER_TOP_LEVEL);
+        }
+    }
+}
+
+// Everything read from the config file, with defaults for anything missing or invalid
+pub struct Config {
+    pub keys: KeyBindings,
+    pub start_level: i32,         // Level a new game starts at
+    pub drop_interval: Duration,  // Time between automatic drops at level 1
+    pub soft_drop_speed: u32,     // Cells per second a held soft drop moves
+    pub soft_drop_lock: SoftDropLock, // Whether soft dropping onto the stack locks at once
+    pub lock_resets: u32,         // Moves and turns that may restart the lock delay of each piece
+    pub gravity: Option<Vec<Duration>>, // Drop interval curve by level, replacing the classic one
+    pub muted: bool,              // Background music is muted
+    pub music_volume: u32,        // Background music level in percent of the effects
+    pub labels: bool,             // Blocks are marked with their piece's letter
+    pub fullscreen: bool,         // The window covers the whole screen
+    pub shake: bool,              // The board shakes on line clears
+    pub patterns: bool,           // Blocks are overlaid with their piece type's pattern
+    pub cell_highlight: bool,     // The grid cell under the mouse is outlined
+    pub difficulty: Difficulty,   // Preset picked on the menu for new games
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            keys: KeyBindings::default(),
+            start_level: MIN_START_LEVEL,
+            drop_interval: DROP_INTERVAL,
+            soft_drop_speed: SOFT_DROP_SPEED,
+            soft_drop_lock: SoftDropLock::Delayed,
+            lock_resets: LOCK_RESETS,
+            gravity: None,
+            muted: false,
+            music_volume: MUSIC_VOLUME,
+            labels: false,
+            fullscreen: false,
+            shake: true,
+            patterns: false,
+            cell_highlight: false,
+            difficulty: Difficulty::Custom,
+        }
+    }
+}
+
+impl Config {
+    // Read the config file, warning about lines that can't be used
+    pub fn load(path: &str) -> Self {
+        let mut config = Config::default();
+        let Ok(contents) = fs::read_to_string(path) else {
+            return config;
+        };
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Err(e) = config.apply(line) {
+                eprintln!("{}:{}: {}", path, number + 1, e);
+            }
+        }
+        config
+    }
+
+    // Apply a single `name = value` line
+    fn apply(&mut self, line: &str) -> Result<(), String> {
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("expected 'name = value', got '{}'", line))?;
+        let (name, value) = (name.trim(), value.trim());
+        match name {
+            "start_level" => {
+                self.start_level = parse_in_range(value, MIN_START_LEVEL as u64, MAX_START_LEVEL as u64)? as i32;
+            }
+            "drop_interval" => {
+                let min = MIN_DROP_SETTING.as_millis() as u64;
+                let max = MAX_DROP_SETTING.as_millis() as u64;
+                self.drop_interval = Duration::from_millis(parse_in_range(value, min, max)?);
+            }
+            "soft_drop_speed" => {
+                let (min, max) = (MIN_SOFT_DROP_SPEED as u64, MAX_SOFT_DROP_SPEED as u64);
+                self.soft_drop_speed = parse_in_range(value, min, max)? as u32;
+            }
+            "soft_drop_lock" => {
+                self.soft_drop_lock = match value {
+                    "delayed" => SoftDropLock::Delayed,
+                    "instant" => SoftDropLock::Instant,
+                    _ => return Err(format!("expected delayed or instant, got '{}'", value)),
+                };
+            }
+            "lock_resets" => self.lock_resets = parse_in_range(value, 0, MAX_LOCK_RESETS as u64)? as u32,
+            "gravity" => {
+                // Drop intervals in ms for levels 1, 2, 3 and so on, separated by commas
+                let entries = value
+                    .split(',')
+                    .map(|entry| parse_in_range(entry.trim(), 1, MAX_GRAVITY_ENTRY).map(Duration::from_millis))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.gravity = Some(entries);
+            }
+            "muted" => self.muted = parse_bool(value)?,
+            "music_volume" => self.music_volume = parse_in_range(value, 0, MAX_MUSIC_VOLUME as u64)? as u32,
+            "labels" => self.labels = parse_bool(value)?,
+            "fullscreen" => self.fullscreen = parse_bool(value)?,
+            "shake" => self.shake = parse_bool(value)?,
+            "patterns" => self.patterns = parse_bool(value)?,
+            "cell_highlight" => self.cell_highlight = parse_bool(value)?,
+            "difficulty" => {
+                self.difficulty = Difficulty::ALL
+                    .into_iter()
+                    .find(|preset| preset.name() == value)
+                    .ok_or_else(|| format!("expected beginner, standard, expert or custom, got '{}'", value))?;
+            }
+            _ => self.keys.bind(name, value)?,
+        }
+        Ok(())
+    }
+
+    // Write the settings back to the config file, keeping key bindings and comments intact
+    pub fn save_settings(&self, path: &str) -> Result<(), String> {
+        let values = [
+            ("start_level", self.start_level.to_string()),
+            ("drop_interval", self.drop_interval.as_millis().to_string()),
+            ("soft_drop_speed", self.soft_drop_speed.to_string()),
+            ("soft_drop_lock", self.soft_drop_lock.name().to_string()),
+            ("lock_resets", self.lock_resets.to_string()),
+            ("muted", self.muted.to_string()),
+            ("music_volume", self.music_volume.to_string()),
+            ("labels", self.labels.to_string()),
+            ("fullscreen", self.fullscreen.to_string()),
+            ("shake", self.shake.to_string()),
+            ("patterns", self.patterns.to_string()),
+            ("cell_highlight", self.cell_highlight.to_string()),
+            ("difficulty", self.difficulty.name().to_string()),
+        ];
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+        for (name, value) in values {
+            let setting = format!("{} = {}", name, value);
+            let existing = lines
+                .iter_mut()
+                .find(|line| line.split_once('=').is_some_and(|(key, _)| key.trim() == name));
+            match existing {
+                Some(line) => *line = setting,
+                None => lines.push(setting),
+            }
+        }
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        fs::write(path, contents).map_err(|e| format!("could not write {}: {}", path, e))
+    }
+}
+
+// Parse a whole number within min..=max
+fn parse_in_range(value: &str, min: u64, max: u64) -> Result<u64, String> {
+    match value.parse() {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(format!("expected a whole number from {} to {}, got '{}'", min, max, value)),
+    }
+}
+
+// Parse true or false
+fn parse_bool(value: &str) -> Result<bool, String> {
+    value.parse().map_err(|_| format!("expected true or false, got '{}'", value))
+}
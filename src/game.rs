@@ -0,0 +1,2645 @@
+// Core game logic, kept free of ggez so it can be driven and tested without a window
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::time::Duration;
+
+use crate::leaderboard::{Leaderboard, LEADERBOARD_FILE};
+use crate::pieces::{Kicks, PieceDef};
+
+// Game constants
+pub const DEFAULT_GRID_WIDTH: i32 = 10;
+pub const DEFAULT_GRID_HEIGHT: i32 = 20;
+pub const DROP_INTERVAL: Duration = Duration::from_millis(500); // Default time between automatic drops at level 1
+pub const SOFT_DROP_SPEED: u32 = 10; // Default soft drop speed in cells per second
+// Frames per automatic drop at each level from 1 up in the classic NES game, which runs at 60 frames
+// a second; levels past the end keep the last speed
+const CLASSIC_GRAVITY: [u64; 30] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1,
+];
+const LINES_PER_LEVEL: i32 = 10; // Lines needed to advance one level
+pub const REPLAY_FILE: &str = "last.replay"; // Replay of the most recent game, in the working directory
+const DAS_DELAY: Duration = Duration::from_millis(170); // Hold time before a direction starts repeating
+const ARR_RATE: Duration = Duration::from_millis(50); // Time between repeated moves once auto shift kicks in
+const CLEAR_ANIMATION: Duration = Duration::from_millis(200); // How long full rows flash before collapsing
+const COLLAPSE_ANIMATION: Duration = Duration::from_millis(150); // How long the rows above a clear take to fall into place
+const LOCK_DELAY: Duration = Duration::from_millis(500); // Time a landed piece may still slide before locking
+pub const LOCK_RESETS: u32 = 15; // Default moves and turns that may restart the lock delay for each piece
+const COUNTDOWN: Duration = Duration::from_secs(3); // Default wait before play starts, counted down on screen
+pub const NEXT_PIECES: usize = 3; // Upcoming pieces kept in the queue and shown in the preview
+const LOCK_FLASH: Duration = Duration::from_millis(50); // How long a locking piece flashes before it freezes
+const PARTICLE_LIFETIME: Duration = Duration::from_millis(300); // How long a hard drop trail lingers
+const MAX_PARTICLES: usize = 80; // Oldest trail particles are dropped beyond this
+const POPUP_LIFETIME: Duration = Duration::from_millis(900); // How long a score popup floats before vanishing
+const POPUP_RISE: f32 = 2.0; // Rows a score popup floats up over its lifetime
+const SHAKE_DURATION: Duration = Duration::from_millis(250); // How long the board shakes after a line clear
+const SHAKE_PER_LINE: f32 = 0.05; // Shake strength in cells for each line cleared
+const TETRIS_SHAKE: f32 = 0.1; // Extra shake strength for a tetris, up to 0.3 cells in all
+const POPUP_TOP: f32 = 2.0; // Popups stay below this many top rows, clear of the score header
+
+// SRS wall kick offsets for clockwise rotation, indexed by the rotation state being left.
+// Offsets are in grid coordinates (positive y points down), tried in order.
+const JLSTZ_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], // 0 -> R
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],   // R -> 2
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],    // 2 -> L
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // L -> 0
+];
+const I_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],  // 0 -> R
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],  // R -> 2
+    [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],  // 2 -> L
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],  // L -> 0
+];
+
+// RGBA color of a block, converted to a graphics color by the renderer
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const RED: Color = Color::new(1.0, 0.0, 0.0, 1.0);
+    pub const GREEN: Color = Color::new(0.0, 1.0, 0.0, 1.0);
+    pub const YELLOW: Color = Color::new(1.0, 1.0, 0.0, 1.0);
+    pub const CYAN: Color = Color::new(0.0, 1.0, 1.0, 1.0);
+    pub const MAGENTA: Color = Color::new(1.0, 0.0, 1.0, 1.0);
+    pub const WHITE: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+    pub const ORANGE: Color = Color::new(1.0, 0.5, 0.0, 1.0);
+    pub const GRAY: Color = Color::new(0.5, 0.5, 0.5, 1.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color { r, g, b, a }
+    }
+}
+
+// Logical player actions, mapped from physical keys by the frontend
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Hold,
+    Pause,
+    Restart,
+}
+
+impl Action {
+    // Movement acts for as long as its key is held; everything else triggers once per press
+    pub fn is_continuous(self) -> bool {
+        matches!(self, Action::MoveLeft | Action::MoveRight | Action::SoftDrop)
+    }
+}
+
+// Input for a single update: actions currently held and actions pressed since the last update
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Input {
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub held: HashSet<Action>,
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub pressed: HashSet<Action>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_column: Option<i32>, // Column to steer the piece toward, e.g. under the mouse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forced_piece: Option<i32>, // Practice mode: piece type to spawn next instead of the queue's
+}
+
+// One update's input, timed from the start of the game
+#[derive(Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub time: Duration,
+    pub input: Input,
+}
+
+// Everything needed to play a game back exactly: its options, including the piece seed,
+// and the input of every update
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub config: GameConfig,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        serde_json::from_str(&json).map_err(|e| format!("{} is not a valid replay: {}", path, e))
+    }
+}
+
+// Notable things that happened during an update, drained by the frontend for sound and effects
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEvent {
+    PieceLocked,
+    LinesCleared(i32),
+    Rotated,
+    TSpin(i32), // A T-spin line clear, with the number of lines
+    PerfectClear, // A line clear left the grid completely empty
+    StackReset,   // Zen mode cleared the bottom of a topped-out stack to carry on
+    LevelUp(i32), // A line clear reached a new level, given here
+    GameOver,
+}
+
+// Hooks for code embedding the game, such as a bot or another frontend, called from the game
+// logic as things happen. Every method does nothing unless overridden.
+pub trait GameObserver {
+    // A piece has locked into the grid
+    fn on_lock(&mut self) {}
+    // Lines have been cleared, counting how many
+    fn on_line_clear(&mut self, _lines: i32) {}
+    // A line clear has reached a new level
+    fn on_level_up(&mut self, _level: i32) {}
+    // The game has ended, whether topped out or won; `won` on the game tells which
+    fn on_game_over(&mut self) {}
+}
+
+// Color scheme for the seven piece types
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Theme {
+    Classic,
+    Pastel,
+    Grayscale,
+}
+
+impl Theme {
+    // Piece colors indexed by block_type (I, O, L, J, T, S, Z)
+    pub fn palette(self) -> [Color; 7] {
+        match self {
+            Theme::Classic => [
+                Color::CYAN,
+                Color::YELLOW,
+                Color::RED,
+                Color::GREEN,
+                Color::MAGENTA,
+                Color::WHITE,
+                Color::ORANGE,
+            ],
+            Theme::Pastel => [
+                Color::new(0.6, 0.9, 0.95, 1.0),
+                Color::new(1.0, 0.95, 0.6, 1.0),
+                Color::new(1.0, 0.65, 0.65, 1.0),
+                Color::new(0.65, 0.9, 0.65, 1.0),
+                Color::new(0.85, 0.7, 0.95, 1.0),
+                Color::new(0.95, 0.9, 0.85, 1.0),
+                Color::new(1.0, 0.8, 0.6, 1.0),
+            ],
+            // Evenly spaced brightness levels so pieces stay distinguishable without hue
+            Theme::Grayscale => [
+                Color::new(0.95, 0.95, 0.95, 1.0),
+                Color::new(0.85, 0.85, 0.85, 1.0),
+                Color::new(0.75, 0.75, 0.75, 1.0),
+                Color::new(0.65, 0.65, 0.65, 1.0),
+                Color::new(0.55, 0.55, 0.55, 1.0),
+                Color::new(0.45, 0.45, 0.45, 1.0),
+                Color::new(0.35, 0.35, 0.35, 1.0),
+            ],
+        }
+    }
+
+    // Color of the outline around the playfield
+    pub fn border(self) -> Color {
+        match self {
+            Theme::Classic => Color::new(0.4, 0.4, 0.8, 1.0),
+            Theme::Pastel => Color::new(0.8, 0.7, 0.9, 1.0),
+            Theme::Grayscale => Color::new(0.6, 0.6, 0.6, 1.0),
+        }
+    }
+}
+
+// What happens when a soft drop pushes a piece onto the stack
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SoftDropLock {
+    Delayed, // The lock delay runs as usual, leaving time to slide the piece
+    Instant, // The piece locks at once, as if hard dropped from there
+}
+
+impl SoftDropLock {
+    // The name used in the config file and settings overlay
+    pub fn name(self) -> &'static str {
+        match self {
+            SoftDropLock::Delayed => "delayed",
+            SoftDropLock::Instant => "instant",
+        }
+    }
+}
+
+// The classic NES drop intervals by level
+pub fn classic_gravity() -> Vec<Duration> {
+    CLASSIC_GRAVITY.iter().map(|&frames| Duration::from_secs(frames) / 60).collect()
+}
+
+// Options chosen at launch that shape a game
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameConfig {
+    pub width: i32,  // Grid width in cells
+    pub height: i32, // Visible grid height in cells
+    pub hidden_rows: i32, // Buffer rows above the visible grid that pieces can spawn into
+    pub spawn_offset: (i32, i32), // Shift applied to every piece's spawn position
+    pub theme: Theme, // Piece color scheme
+    pub target_lines: Option<i32>, // Sprint mode: clearing this many lines wins the game
+    pub target_level: Option<i32>, // Marathon mode: reaching this level wins the game
+    pub zen: bool, // Zen mode: topping out clears the bottom of the stack instead of ending the game
+    pub practice: bool, // Practice mode: the player may pick the next piece to spawn
+    pub twenty_g: bool, // 20G mode: pieces fall to the floor at once, leaving only slides and lock delay
+    pub puzzle: bool, // Puzzle mode: pieces never fall on their own, only when dropped
+    pub start_level: i32, // Level a new game starts at
+    pub drop_interval: Duration, // Time between automatic drops at level 1
+    pub gravity: Vec<Duration>, // Drop interval curve by level from 1 up, scaled to start at drop_interval
+    pub soft_drop_speed: u32, // Cells per second a held soft drop moves, at any level
+    pub soft_drop_lock: SoftDropLock, // Whether soft dropping onto the stack locks at once or after the lock delay
+    pub lock_delay: Duration, // How long a landed piece waits before locking
+    pub lock_reset_limit: u32, // Moves and turns that may restart the lock delay before the piece locks regardless
+    pub das_delay: Duration, // Hold time before a direction starts repeating
+    pub arr_rate: Duration, // Time between repeated moves once auto shift kicks in
+    pub garbage_rows: i32, // Bottom rows pre-filled with garbage for digging practice
+    pub seed: Option<u64>, // Piece and garbage seed; a fresh random one for each game if unset
+    pub pieces: Option<Vec<PieceDef>>, // Alternate piece set; the standard seven in theme colors if unset
+    pub countdown: Duration, // Game time before gravity and input start
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            width: DEFAULT_GRID_WIDTH,
+            height: DEFAULT_GRID_HEIGHT,
+            hidden_rows: 0,
+            spawn_offset: (0, 0),
+            theme: Theme::Classic,
+            target_lines: None,
+            target_level: None,
+            zen: false,
+            practice: false,
+            twenty_g: false,
+            puzzle: false,
+            start_level: 1,
+            drop_interval: DROP_INTERVAL,
+            gravity: classic_gravity(),
+            soft_drop_speed: SOFT_DROP_SPEED,
+            soft_drop_lock: SoftDropLock::Delayed,
+            lock_delay: LOCK_DELAY,
+            lock_reset_limit: LOCK_RESETS,
+            das_delay: DAS_DELAY,
+            arr_rate: ARR_RATE,
+            garbage_rows: 0,
+            seed: None,
+            pieces: None,
+            countdown: COUNTDOWN,
+        }
+    }
+}
+
+// Base points for each kind of line clear, multiplied by the level they are scored at
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ScoreTable {
+    pub lines: [i32; 4], // Single, double, triple and tetris
+    pub tspin: [i32; 3], // T-spin single, double and triple
+    pub perfect_clear: i32, // Flat bonus for a clear that empties the grid
+}
+
+impl Default for ScoreTable {
+    fn default() -> Self {
+        ScoreTable { lines: [100, 300, 500, 800], tspin: [800, 1200, 1600], perfect_clear: 3000 }
+    }
+}
+
+// Points earned by the most recent line clear, itemized for display
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ClearScore {
+    pub lines: i32,        // Rows cleared
+    pub tspin: bool,       // Cleared with a T-spin
+    pub base: i32,         // Table points for the clear, before the level multiplier
+    pub level: i32,        // Level the clear was scored at
+    pub back_to_back: i32, // Bonus for a tetris following another tetris
+    pub combo: i32,        // Bonus for consecutive clearing locks
+    pub perfect_clear: i32, // Bonus for leaving the grid empty
+}
+
+impl ClearScore {
+    // Everything the clear added to the score
+    pub fn total(&self) -> i32 {
+        self.base * self.level + self.back_to_back + self.combo + self.perfect_clear
+    }
+}
+
+// Purely visual trail left behind by a hard drop; never affects play
+#[derive(Clone, Copy)]
+pub struct Particle {
+    pub x: i32,          // Grid cell the particle sits in
+    pub y: i32,
+    pub color: Color,
+    pub life: f32,       // Remaining lifetime as a fraction, from 1.0 down to 0.0
+    spawned: Duration,   // Game time the particle appeared
+}
+
+// Purely visual points earned by a line clear, floating up from the cleared rows
+#[derive(Clone)]
+pub struct Popup {
+    pub text: String,
+    pub x: f32,          // Grid position of the text's center, in cells
+    pub y: f32,
+    pub life: f32,       // Remaining lifetime as a fraction, from 1.0 down to 0.0
+    start_y: f32,        // Row the popup appeared at
+    spawned: Duration,   // Game time the popup appeared
+}
+
+// Purely visual shake of the board after a line clear, dying away over SHAKE_DURATION
+#[derive(Clone, Copy)]
+struct Shake {
+    strength: f32,     // Largest offset in cells, at the start
+    started: Duration, // Game time the shake started
+}
+
+// A filled grid cell
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cell {
+    pub color: Color,
+    pub block_type: Option<i32>, // Type of the piece the cell was frozen from; None for garbage
+}
+
+// Represents a single block in the game
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Block {
+    pub x: i32,
+    pub y: i32,
+    pub color: Color,
+}
+
+// Represents a complete piece: four blocks for a tetromino, or however many its definition has
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tetromino {
+    pub blocks: Vec<Block>,
+    pub block_type: i32, // Index of the piece's definition in the game's piece set
+    rotation: u8,    // Rotation state: 0 = spawn, 1 = R, 2 = 180, 3 = L
+    pivot: (i32, i32), // Rotation center in half-cell units (doubled coordinates)
+}
+
+impl Tetromino {
+    // Current orientation: 0 = spawn, 1 = R, 2 = 180, 3 = L
+    pub fn rotation(&self) -> u8 {
+        self.rotation
+    }
+
+    // Move all blocks and the rotation center by the specified amount
+    pub fn translate(&mut self, dx: i32, dy: i32) {
+        for block in &mut self.blocks {
+            block.x += dx;
+            block.y += dy;
+        }
+        self.pivot = (self.pivot.0 + dx * 2, self.pivot.1 + dy * 2);
+    }
+
+    // A copy of the piece moved by the specified amount, leaving this one where it is
+    pub fn translated(&self, dx: i32, dy: i32) -> Tetromino {
+        let mut moved = self.clone();
+        moved.translate(dx, dy);
+        moved
+    }
+
+    // Grid coordinates of every block
+    pub fn cells(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.blocks.iter().map(|block| (block.x, block.y))
+    }
+
+    // The smallest rectangle covering the blocks, as (min_x, min_y, max_x, max_y)
+    pub fn bounding_box(&self) -> (i32, i32, i32, i32) {
+        self.cells().fold((i32::MAX, i32::MAX, i32::MIN, i32::MIN), |(min_x, min_y, max_x, max_y), (x, y)| {
+            (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+        })
+    }
+
+    // The piece turned 90 degrees around its pivot, in place with no wall kicks
+    pub fn rotated(&self, clockwise: bool) -> Tetromino {
+        // Work in half-cell units so the I piece can turn around a grid corner
+        let (center_x, center_y) = self.pivot;
+        let blocks = self.blocks.iter()
+            .map(|block| {
+                // Calculate new position after rotation
+                let dx = block.x * 2 - center_x;
+                let dy = block.y * 2 - center_y;
+                let (new_x, new_y) = if clockwise {
+                    (center_x - dy, center_y + dx)
+                } else {
+                    (center_x + dy, center_y - dx)
+                };
+                Block { x: new_x / 2, y: new_y / 2, color: block.color }
+            })
+            .collect();
+        let rotation = if clockwise { (self.rotation + 1) % 4 } else { (self.rotation + 3) % 4 };
+        Tetromino { blocks, rotation, ..*self }
+    }
+}
+
+// Main game state structure, serialized as a whole for save games
+#[derive(Serialize, Deserialize)]
+pub struct GameState {
+    config: GameConfig,            // Launch options, kept for restarts
+    pub width: i32,                // Grid width in cells
+    pub height: i32,               // Grid height in cells, including the hidden rows
+    pub hidden_rows: i32,          // Rows at the top of the grid that are not drawn
+    pieces: Vec<PieceDef>,         // Shape and color of each piece type, indexed by block_type
+    pub tetromino: Tetromino,      // Current falling piece
+    pub next_pieces: VecDeque<i32>, // Types of the upcoming pieces, soonest first
+    pub held_tetromino: Option<i32>, // Type of the piece stashed with hold, if any
+    pub hold_used: bool,           // Hold may only be used once per piece until it locks
+    pub opening_swapped: bool,     // The first piece has been swapped with the next one during the countdown
+    bag: Vec<i32>,                 // Remaining piece types in the current bag of one of each
+    pub grid: Vec<Vec<Option<Cell>>>, // Game grid: None = empty, Some(Cell) = filled
+    pub game_over: bool,           // Game over flag
+    pub won: bool,                 // Sprint or marathon target reached; set together with game_over
+    pub zen: bool,                 // Topping out clears the bottom of the stack instead of ending the game
+    pub practice: bool,            // The player may pick the next piece to spawn
+    pub forced_next: Option<i32>,  // Piece type picked to spawn next, ahead of the queue
+    pub twenty_g: bool,            // Pieces fall to the floor at once, every update
+    pub puzzle: bool,              // Pieces only fall when the player drops them
+    pub score: i32,                // Current score
+    pub high_score: i32,           // Best score across runs, from the top of the leaderboard
+    pub level: i32,                // Current level, counting up from the configured start level
+    pub total_lines: i32,          // Total lines cleared this game
+    pub last_clear_was_tetris: bool, // Back-to-back chain is alive: the next tetris earns a bonus
+    tspin: bool,                   // Last maneuver was a kicked T rotation into a three-corner spot
+    pub pieces_placed: u32,        // Pieces locked into the grid this game
+    pub spawn_counts: Vec<u32>,    // Pieces of each type dealt this game, indexed by block_type
+    pub combo: i32,                // Consecutive line-clearing locks minus one, -1 when inactive
+    pub scoring: ScoreTable,       // Base points per kind of line clear
+    pub last_clear: Option<ClearScore>, // Breakdown of the most recent line clear's points
+    base_drop_interval: Duration,  // Time between automatic drops at level 1, adjustable in play
+    gravity: Vec<Duration>,        // Drop interval curve by level, indexed by level - 1
+    pub das_delay: Duration,       // Delayed auto shift: hold time before horizontal repeat starts
+    pub arr_rate: Duration,        // Auto repeat rate: time between repeated horizontal moves
+    shift_direction: i32,          // Horizontal direction currently held: -1, 0 or 1
+    shift_started: Duration,       // Time the current horizontal direction was first pressed
+    last_move_time: Duration,      // Time of last horizontal movement
+    last_soft_drop_time: Duration, // Time of last soft drop step
+    last_drop_time: Duration,      // Time of last automatic drop
+    pub lock_delay: Duration,      // How long a landed piece waits before locking
+    lock_timer: Option<Duration>,  // Time the current piece came to rest, if it is resting
+    lock_resets: u32,              // Times the current piece has restarted its lock delay
+    lock_flash_started: Option<Duration>, // Time the current piece started its lock flash, if locking
+    game_time: Duration,           // Game clock: play time so far, advancing only while unpaused
+    pub clearing_rows: Vec<i32>,   // Full rows flashing before they are removed
+    clear_started: Duration,       // Time the line-clear flash started
+    collapse_drops: Vec<usize>,    // Rows each grid row fell in the latest clear, while they are still falling
+    collapse_started: Duration,    // Time the rows above the latest clear started falling
+    pub paused: bool,              // Pause flag
+    #[serde(skip)]
+    events: Vec<GameEvent>,        // Events raised since the frontend last drained them
+    #[serde(skip)]
+    observer: Option<Box<dyn GameObserver>>, // Hooks registered by the embedding code, if any
+    #[serde(skip)]
+    time_scale: Option<f32>,       // Debug slow motion or fast forward of the game clock; None runs in real time
+    #[serde(skip)]
+    pub particles: Vec<Particle>,  // Fading hard drop trail, oldest first
+    #[serde(skip)]
+    shake: Option<Shake>,          // Board shake from the latest line clear, if it is still going
+    #[serde(skip)]
+    pub popups: Vec<Popup>,        // Points from recent line clears, floating up and fading
+    seed: u64,                     // Seed the piece generator started from
+    #[serde(skip, default = "StdRng::from_entropy")]
+    rng: StdRng,                   // Piece and garbage randomness; loaded games continue unseeded
+    #[serde(skip)]
+    recording: bool,               // Whether this game's input is recorded for a replay
+    #[serde(skip)]
+    last_update: Duration,         // Real time of the previous update, to measure each step by
+    #[serde(skip)]
+    clock_start: Duration,         // Time passed to start_clock, the origin of replay frame times
+    #[serde(skip)]
+    replay: Vec<ReplayFrame>,      // Input of every update so far, written out when the game ends
+}
+
+impl GameState {
+    // Initialize a new game state
+    pub fn new(config: GameConfig) -> Self {
+        let (width, height, hidden_rows) = (config.width, config.height + config.hidden_rows, config.hidden_rows);
+        let (zen, practice, twenty_g, puzzle) = (config.zen, config.practice, config.twenty_g, config.puzzle);
+        let (level, base_drop_interval) = (config.start_level, config.drop_interval);
+        let gravity = config.gravity.clone();
+        let (lock_delay, das_delay, arr_rate) = (config.lock_delay, config.das_delay, config.arr_rate);
+        let seed = config.seed.unwrap_or_else(rand::random);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut grid = vec![vec![None; width as usize]; height as usize];
+        Self::fill_garbage(&mut grid, config.garbage_rows, &mut rng);
+        let mut bag = Vec::new();
+        let pieces = config.pieces.clone().unwrap_or_else(|| crate::pieces::standard(config.theme));
+        let tetromino = Self::build_tetromino(Self::next_from_bag(&mut bag, pieces.len(), &mut rng), &config, &pieces);
+        let next_pieces = (0..NEXT_PIECES).map(|_| Self::next_from_bag(&mut bag, pieces.len(), &mut rng)).collect();
+        let mut spawn_counts = vec![0; pieces.len()];
+        spawn_counts[tetromino.block_type as usize] += 1;
+        
+        GameState {
+            config,
+            width,
+            height,
+            hidden_rows,
+            pieces,
+            tetromino,
+            next_pieces,
+            held_tetromino: None,
+            hold_used: false,
+            opening_swapped: false,
+            bag,
+            grid,
+            game_over: false,
+            won: false,
+            zen,
+            practice,
+            forced_next: None,
+            twenty_g,
+            puzzle,
+            score: 0,
+            high_score: Self::load_high_score(),
+            level,
+            total_lines: 0,
+            last_clear_was_tetris: false,
+            tspin: false,
+            pieces_placed: 0,
+            spawn_counts,
+            combo: -1,
+            scoring: ScoreTable::default(),
+            last_clear: None,
+            base_drop_interval,
+            gravity,
+            das_delay,
+            arr_rate,
+            shift_direction: 0,
+            shift_started: Duration::ZERO,
+            last_move_time: Duration::ZERO,
+            last_soft_drop_time: Duration::ZERO,
+            last_drop_time: Duration::ZERO,
+            lock_delay,
+            lock_timer: None,
+            lock_resets: 0,
+            lock_flash_started: None,
+            game_time: Duration::ZERO,
+            clearing_rows: Vec::new(),
+            clear_started: Duration::ZERO,
+            collapse_drops: Vec::new(),
+            collapse_started: Duration::ZERO,
+            paused: false,
+            events: Vec::new(),
+            observer: None,
+            time_scale: None,
+            particles: Vec::new(),
+            popups: Vec::new(),
+            shake: None,
+            seed,
+            rng,
+            recording: true,
+            last_update: Duration::ZERO,
+            clock_start: Duration::ZERO,
+            replay: Vec::new(),
+        }
+    }
+
+    // Seed this game's pieces were generated from; passing it back in reproduces them
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Start a game that plays back a replay; its own input isn't recorded again
+    pub fn from_replay(replay: &Replay) -> Self {
+        let mut game = GameState::new(replay.config.clone());
+        game.recording = false;
+        game
+    }
+
+    // Fill the bottom rows with garbage, each with a single gap in a different column from
+    // the row below it
+    fn fill_garbage(grid: &mut [Vec<Option<Cell>>], rows: i32, rng: &mut StdRng) {
+        let width = grid.first().map_or(0, |row| row.len());
+        let mut last_gap = None;
+        for row in grid.iter_mut().rev().take(rows.max(0) as usize) {
+            let mut gap = rng.gen_range(0..width);
+            while width > 1 && Some(gap) == last_gap {
+                gap = rng.gen_range(0..width);
+            }
+            for (x, cell) in row.iter_mut().enumerate() {
+                *cell = (x != gap).then_some(Cell { color: Color::GRAY, block_type: None });
+            }
+            last_gap = Some(gap);
+        }
+    }
+
+    // Take the piece at the front of the queue, topping the queue back up from the bag; a
+    // piece picked in practice mode comes first and leaves the queue as it was
+    fn take_next_piece(&mut self) -> Tetromino {
+        if let Some(block_type) = self.forced_next.take() {
+            self.spawn_counts[block_type as usize] += 1;
+            return self.create_tetromino(block_type);
+        }
+        while self.next_pieces.len() <= NEXT_PIECES {
+            let block_type = Self::next_from_bag(&mut self.bag, self.pieces.len(), &mut self.rng);
+            self.next_pieces.push_back(block_type);
+        }
+        let block_type = self.next_pieces.pop_front().unwrap();
+        self.spawn_counts[block_type as usize] += 1;
+        self.create_tetromino(block_type)
+    }
+
+    // The next piece types to spawn, soonest first, including a piece picked in practice mode
+    pub fn upcoming(&self) -> Vec<i32> {
+        self.forced_next.iter().chain(&self.next_pieces).take(NEXT_PIECES).copied().collect()
+    }
+
+    // Pop the next piece type, refilling the bag with a shuffled set of every type when empty
+    fn next_from_bag(bag: &mut Vec<i32>, kinds: usize, rng: &mut StdRng) -> i32 {
+        if bag.is_empty() {
+            bag.extend(0..kinds as i32);
+            bag.shuffle(rng);
+        }
+        bag.pop().unwrap()
+    }
+
+    // Create a piece of the given type at its spawn position, from this game's piece set
+    pub fn create_tetromino(&self, block_type: i32) -> Tetromino {
+        Self::build_tetromino(block_type, &self.config, &self.pieces)
+    }
+
+    // Build a piece of the given type from its definition at its spawn position: centered the
+    // way the standard pieces spawn on a 10-wide grid, with its top row in the lowest hidden
+    // row so only part of it shows, then shifted by the piece's own and the game's offsets
+    fn build_tetromino(block_type: i32, config: &GameConfig, pieces: &[PieceDef]) -> Tetromino {
+        let piece = &pieces[block_type.clamp(0, pieces.len() as i32 - 1) as usize];
+        let mut tetromino = Tetromino {
+            blocks: piece.cells.iter()
+                        .map(|&(x, y)| Block { x, y, color: piece.color })
+                        .collect(),
+            block_type,
+            rotation: 0,
+            pivot: piece.pivot,
+        };
+        let x = config.width / 2 - (piece.width() + 1) / 2 + piece.spawn.0 + config.spawn_offset.0;
+        let y = (config.hidden_rows - 1).max(0) + piece.spawn.1 + config.spawn_offset.1;
+        tetromino.translate(x, y);
+        tetromino
+    }
+
+    // Rotate the current tetromino 90 degrees clockwise
+    fn rotate_tetromino(&mut self) {
+        self.rotate(true);
+    }
+
+    // Rotate the current tetromino 90 degrees counter-clockwise
+    fn rotate_tetromino_ccw(&mut self) {
+        self.rotate(false);
+    }
+
+    // Rotate the current tetromino 90 degrees, trying SRS wall kicks if blocked
+    fn rotate(&mut self, clockwise: bool) {
+        let piece = &self.pieces[self.tetromino.block_type as usize];
+        let (kicks, is_t) = match piece.kicks {
+            Kicks::None => return, // The square doesn't need rotation
+            Kicks::Standard => (&JLSTZ_KICKS, piece.name == "T"),
+            Kicks::Long => (&I_KICKS, false),
+        };
+
+        let rotated = self.tetromino.rotated(clockwise);
+        let rotation = self.tetromino.rotation();
+        let new_rotation = rotated.rotation();
+        // Counter-clockwise kicks are the clockwise kicks of the reverse transition, negated
+        let (table, sign) = if clockwise { (rotation, 1) } else { (new_rotation, -1) };
+
+        // Apply the first kick offset that gives a valid placement
+        for (kick, &(kick_x, kick_y)) in kicks[table as usize].iter().enumerate() {
+            let (kick_x, kick_y) = (kick_x * sign, kick_y * sign);
+            let kicked = rotated.translated(kick_x, kick_y);
+            if self.fits(&kicked) {
+                self.tetromino = kicked;
+                // A T piece kicked into a spot with three filled corners is a T-spin
+                self.tspin = is_t && kick > 0 && self.filled_corners() >= 3;
+                self.reset_lock_timer();
+                self.emit(GameEvent::Rotated);
+                return;
+            }
+        }
+    }
+
+    // Count the diagonal corners around the piece's center that are walls, floor or blocks
+    fn filled_corners(&self) -> usize {
+        let (center_x, center_y) = (self.tetromino.pivot.0 / 2, self.tetromino.pivot.1 / 2);
+        [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .iter()
+            .filter(|&&(dx, dy)| {
+                let (x, y) = (center_x + dx, center_y + dy);
+                if !(0..self.width).contains(&x) || y >= self.height {
+                    return true;
+                }
+                y >= 0 && self.grid[y as usize][x as usize].is_some()
+            })
+            .count()
+    }
+
+    // Check whether every cell is inside the walls, above the floor and empty; cells above the
+    // top of the grid count as free so pieces can spawn and rotate there
+    fn is_valid_position(&self, cells: &[(i32, i32)]) -> bool {
+        cells.iter().all(|&(x, y)| {
+            if !(0..self.width).contains(&x) || y >= self.height {
+                return false;
+            }
+
+            y < 0 || self.grid[y as usize][x as usize].is_none()
+        })
+    }
+
+    // Check whether a candidate placement of a piece fits on the grid
+    fn fits(&self, piece: &Tetromino) -> bool {
+        self.is_valid_position(&piece.cells().collect::<Vec<_>>())
+    }
+
+    // Check whether the current tetromino could be moved by the specified amount
+    fn can_move(&self, dx: i32, dy: i32) -> bool {
+        self.fits(&self.tetromino.translated(dx, dy))
+    }
+
+    // Move the current tetromino by the specified amount, returning whether it moved; what
+    // a blocked move means is left to the caller
+    fn move_tetromino(&mut self, dx: i32, dy: i32) -> bool {
+        if !self.can_move(dx, dy) {
+            return false;
+        }
+        self.tetromino.translate(dx, dy);
+        self.tspin = false;
+        self.reset_lock_timer();
+        true
+    }
+
+    // Player-driven drop by one cell, worth 1 point per cell actually descended
+    fn soft_drop(&mut self) {
+        if self.can_move(0, 1) {
+            self.tetromino.translate(0, 1);
+            self.tspin = false;
+            self.score += 1;
+        } else if self.config.soft_drop_lock == SoftDropLock::Instant {
+            self.start_lock_flash();
+        } else {
+            self.start_lock_timer();
+        }
+    }
+
+    // Start the lock delay when the piece comes to rest, unless it is already running; a piece
+    // that has used up its resets locks as soon as it lands again
+    fn start_lock_timer(&mut self) {
+        if self.lock_timer.is_none() {
+            if self.lock_resets >= self.config.lock_reset_limit {
+                self.start_lock_flash();
+            } else {
+                self.lock_timer = Some(self.game_time);
+            }
+        }
+    }
+
+    // Restart a running lock delay after the player successfully moves or rotates the piece,
+    // up to the reset limit; past it a piece still resting on the stack locks at once
+    fn reset_lock_timer(&mut self) {
+        if self.lock_timer.is_some() {
+            if self.lock_resets < self.config.lock_reset_limit {
+                self.lock_resets += 1;
+                self.lock_timer = Some(self.game_time);
+            } else if !self.can_move(0, 1) {
+                self.start_lock_flash();
+            }
+        }
+    }
+
+    // Freeze the piece once it has rested for the full lock delay
+    fn update_lock(&mut self) {
+        if let Some(landed_at) = self.lock_timer {
+            if self.can_move(0, 1) {
+                // Slid off the ledge, so it falls again
+                self.lock_timer = None;
+            } else if self.game_time - landed_at >= self.lock_delay {
+                self.start_lock_flash();
+            }
+        }
+    }
+
+    // Number of cells the current tetromino can still fall before it lands
+    pub fn landing_offset(&self) -> i32 {
+        let mut dy = 0;
+        while self.can_move(0, dy + 1) {
+            dy += 1;
+        }
+        dy
+    }
+
+    // Drop the current tetromino straight down as far as it goes and freeze it
+    fn hard_drop(&mut self) {
+        let dy = self.landing_offset();
+        self.spawn_trail(dy);
+        self.tetromino.translate(0, dy);
+        if dy > 0 {
+            self.tspin = false;
+        }
+        self.score += dy * 2; // 2 points per cell dropped
+        self.start_lock_flash();
+    }
+
+    // Flash the landed piece briefly; it is frozen into the grid once the flash ends
+    fn start_lock_flash(&mut self) {
+        self.lock_timer = None;
+        self.lock_flash_started = Some(self.game_time);
+    }
+
+    // Whether the current piece has landed and is flashing before it freezes
+    pub fn is_lock_flashing(&self) -> bool {
+        self.lock_flash_started.is_some()
+    }
+
+    // Leave a trail in each column the current piece is about to fall dy cells through
+    fn spawn_trail(&mut self, dy: i32) {
+        for block in &self.tetromino.blocks {
+            // Only the top block of each column starts a trail, so trails don't double up
+            if self.tetromino.blocks.iter().any(|b| b.x == block.x && b.y < block.y) {
+                continue;
+            }
+            for y in block.y.max(0)..block.y + dy {
+                self.particles.push(Particle { x: block.x, y, color: block.color, life: 1.0, spawned: self.game_time });
+            }
+        }
+        if self.particles.len() > MAX_PARTICLES {
+            let excess = self.particles.len() - MAX_PARTICLES;
+            self.particles.drain(..excess);
+        }
+    }
+
+    // Fade particles with age and drop the ones that have expired
+    fn update_particles(&mut self) {
+        let now = self.game_time;
+        for particle in &mut self.particles {
+            let age = now.saturating_sub(particle.spawned);
+            particle.life = 1.0 - age.as_secs_f32() / PARTICLE_LIFETIME.as_secs_f32();
+        }
+        self.particles.retain(|particle| particle.life > 0.0);
+    }
+
+    // Show the points from a line clear centered on the cleared rows, low enough that the
+    // popup never rises into the score header at the top of the visible rows
+    fn spawn_popup(&mut self, points: i32, row: f32) {
+        let start_y = row.max(self.hidden_rows as f32 + POPUP_TOP + POPUP_RISE);
+        self.popups.push(Popup {
+            text: format!("+{}", points),
+            x: self.width as f32 / 2.0,
+            y: start_y,
+            life: 1.0,
+            start_y,
+            spawned: self.game_time,
+        });
+    }
+
+    // Float popups upwards as they fade and drop the ones that have expired
+    fn update_popups(&mut self) {
+        let now = self.game_time;
+        for popup in &mut self.popups {
+            let age = now.saturating_sub(popup.spawned).as_secs_f32() / POPUP_LIFETIME.as_secs_f32();
+            popup.life = 1.0 - age;
+            popup.y = popup.start_y - POPUP_RISE * age.min(1.0);
+        }
+        self.popups.retain(|popup| popup.life > 0.0);
+    }
+
+    // Freeze the current tetromino in place and create a new one
+    fn freeze_tetromino(&mut self) {
+        self.lock_timer = None;
+        // A piece locking above the top of the grid ends the game without being placed; in zen
+        // mode it falls on into the room made for it instead
+        if self.tetromino.cells().any(|(_, y)| y < 0) {
+            if self.zen {
+                self.reset_stack();
+            } else {
+                self.end_game();
+            }
+            return;
+        }
+        let block_type = Some(self.tetromino.block_type);
+        for block in &self.tetromino.blocks {
+            self.grid[block.y as usize][block.x as usize] = Some(Cell { color: block.color, block_type });
+        }
+        self.emit(GameEvent::PieceLocked);
+        self.pieces_placed += 1;
+
+        // Let full rows flash before collapsing them; the next piece waits for the animation
+        self.clearing_rows = (0..self.height)
+            .filter(|&y| self.grid[y as usize].iter().all(|cell| cell.is_some()))
+            .collect();
+        if self.clearing_rows.is_empty() {
+            self.finish_lock();
+        } else {
+            self.clear_started = self.game_time;
+        }
+    }
+
+    // Whether completed rows are currently flashing, with play suspended
+    pub fn is_clearing(&self) -> bool {
+        !self.clearing_rows.is_empty()
+    }
+
+    // Whether the rows above a clear are still falling into place, with play suspended
+    pub fn is_collapsing(&self) -> bool {
+        !self.collapse_drops.is_empty()
+    }
+
+    // How many rows above its resting place a grid row is drawn while the rows above a clear
+    // fall, speeding up as they go; 0 once they have landed
+    pub fn collapse_offset(&self, row: usize) -> f32 {
+        let Some(&drop) = self.collapse_drops.get(row) else {
+            return 0.0;
+        };
+        let progress = (self.game_time.saturating_sub(self.collapse_started).as_secs_f32() / COLLAPSE_ANIMATION.as_secs_f32()).min(1.0);
+        drop as f32 * (1.0 - progress * progress)
+    }
+
+    // Clear completed lines and spawn the next piece once a lock has fully resolved
+    fn finish_lock(&mut self) {
+        self.clearing_rows.clear();
+        self.clear_lines();
+
+        // Sprint and marathon modes end as soon as their target is reached
+        let sprint_done = self.config.target_lines.is_some_and(|target| self.total_lines >= target);
+        let marathon_done = self.target_level().is_some_and(|target| self.level >= target);
+        if sprint_done || marathon_done {
+            self.won = true;
+            self.game_over = true;
+            self.record_high_score();
+            self.save_replay();
+            // A win has no game over sound, but the observer still hears the game ended
+            self.notify(&GameEvent::GameOver);
+            return;
+        }
+
+        self.tetromino = self.take_next_piece();
+        self.hold_used = false;
+        self.lock_resets = 0;
+        self.check_top_out();
+    }
+
+    // The game is over as soon as a new piece spawns on top of the stack, unless zen mode
+    // makes room for it
+    fn check_top_out(&mut self) {
+        if !self.can_move(0, 0) {
+            if self.zen {
+                self.reset_stack();
+            } else {
+                self.end_game();
+            }
+        }
+    }
+
+    // Make room in a topped-out zen game by dropping the bottom half of the grid, moving the
+    // rest of the stack down; the score and lines carry on
+    fn reset_stack(&mut self) {
+        let rows = (self.height / 2) as usize;
+        self.grid.drain(self.grid.len() - rows..);
+        for _ in 0..rows {
+            self.grid.insert(0, vec![None; self.width as usize]);
+        }
+        self.emit(GameEvent::StackReset);
+    }
+
+    // End the game, recording the high score
+    fn end_game(&mut self) {
+        self.game_over = true;
+        self.record_high_score();
+        self.save_replay();
+        self.emit(GameEvent::GameOver);
+    }
+
+    // Write the recorded input of a finished game to REPLAY_FILE
+    fn save_replay(&mut self) {
+        if !self.recording || self.replay.is_empty() {
+            return;
+        }
+        let replay = Replay {
+            config: GameConfig { seed: Some(self.seed), ..self.config.clone() },
+            frames: std::mem::take(&mut self.replay),
+        };
+        let result = serde_json::to_string(&replay)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(REPLAY_FILE, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            eprintln!("Failed to save replay: {}", e);
+        }
+    }
+
+    // Stash the current piece, swapping in the previously held one (or the next piece)
+    fn hold_piece(&mut self) {
+        if self.hold_used {
+            return;
+        }
+
+        let current_type = self.tetromino.block_type;
+        self.tetromino = match self.held_tetromino {
+            Some(held_type) => self.create_tetromino(held_type),
+            None => self.take_next_piece(),
+        };
+        self.held_tetromino = Some(current_type);
+        self.hold_used = true;
+        self.lock_timer = None;
+        self.lock_resets = 0;
+        self.tspin = false;
+        // The piece swapped in spawns like any other, so it can top out the same way
+        self.check_top_out();
+    }
+
+    // Swap the first piece with the next one in the queue, once, before play starts; unlike
+    // hold it leaves hold free for the first piece
+    fn swap_opening_piece(&mut self) {
+        if self.opening_swapped {
+            return;
+        }
+        let current_type = self.tetromino.block_type;
+        let Some(next_type) = self.next_pieces.front_mut().map(|next| std::mem::replace(next, current_type)) else {
+            return;
+        };
+        self.spawn_counts[current_type as usize] -= 1;
+        self.spawn_counts[next_type as usize] += 1;
+        self.tetromino = self.create_tetromino(next_type);
+        self.opening_swapped = true;
+    }
+
+    // Check for and clear completed lines
+    fn clear_lines(&mut self) {
+        let is_full = |row: &Vec<Option<Cell>>| row.iter().all(Option::is_some);
+        // Middle of the full rows, where the score popup appears
+        let full: Vec<usize> = self.grid.iter().enumerate().filter(|(_, row)| is_full(row)).map(|(y, _)| y).collect();
+        let popup_row = full.iter().sum::<usize>() as f32 / full.len().max(1) as f32 + 0.5;
+
+        // Each surviving row falls by the number of full rows below it, which the frontend
+        // animates before play carries on
+        let mut drops = vec![0; self.grid.len()];
+        for y in (0..self.grid.len()).filter(|y| !full.contains(y)) {
+            let drop = full.iter().filter(|&&full_row| full_row > y).count();
+            drops[y + drop] = drop;
+        }
+        if drops.iter().any(|&drop| drop > 0) {
+            self.collapse_drops = drops;
+            self.collapse_started = self.game_time;
+        }
+
+        // Keep the other rows in order and refill the top with empty ones
+        self.grid.retain(|row| !is_full(row));
+        let empty_rows = std::iter::repeat_n(vec![None; self.width as usize], full.len());
+        self.grid.splice(0..0, empty_rows);
+        let lines_cleared = full.len() as i32;
+
+        if lines_cleared > 0 {
+            self.emit(GameEvent::LinesCleared(lines_cleared));
+        }
+
+        // Advance the combo on every consecutive lock that clears lines
+        let tspin = self.tspin && lines_cleared > 0;
+        self.tspin = false;
+        if lines_cleared == 0 {
+            self.combo = -1;
+            return;
+        }
+        self.combo += 1;
+        if tspin {
+            self.emit(GameEvent::TSpin(lines_cleared));
+        }
+
+        // Score from the table at the current level, with larger awards for T-spins
+        let index = (lines_cleared.clamp(1, 4) - 1) as usize;
+        let base = if tspin { self.scoring.tspin[index.min(2)] } else { self.scoring.lines[index] };
+        let mut clear = ClearScore {
+            lines: lines_cleared,
+            tspin,
+            base,
+            level: self.level,
+            back_to_back: 0,
+            combo: 50 * self.combo * self.level,
+            perfect_clear: 0,
+        };
+
+        // Back-to-back tetrises earn a 50% bonus; any other line clear breaks the chain
+        if lines_cleared == 4 {
+            if self.last_clear_was_tetris {
+                clear.back_to_back = base * self.level / 2;
+            }
+            self.last_clear_was_tetris = true;
+        } else {
+            self.last_clear_was_tetris = false;
+        }
+
+        // Clearing the last blocks off the grid is a perfect clear
+        if self.grid.iter().all(|row| row.iter().all(|cell| cell.is_none())) {
+            clear.perfect_clear = self.scoring.perfect_clear;
+            self.emit(GameEvent::PerfectClear);
+        }
+        self.score += clear.total();
+        self.spawn_popup(clear.total(), popup_row);
+        let strength = SHAKE_PER_LINE * lines_cleared as f32 + if lines_cleared >= 4 { TETRIS_SHAKE } else { 0.0 };
+        self.shake = Some(Shake { strength, started: self.game_time });
+        self.last_clear = Some(clear);
+
+        // Advance a level every LINES_PER_LEVEL lines
+        self.total_lines += lines_cleared;
+        let level = self.level;
+        self.update_level();
+        if self.level > level {
+            self.emit(GameEvent::LevelUp(self.level));
+        }
+    }
+
+    // Read the high score from the leaderboard, defaulting to 0 if there is none yet
+    fn load_high_score() -> i32 {
+        Leaderboard::load(LEADERBOARD_FILE).best()
+    }
+
+    // Raise the high score if the current score beats it; the frontend adds the score to
+    // the leaderboard once the player has entered a name
+    fn record_high_score(&mut self) {
+        self.high_score = self.high_score.max(self.score);
+    }
+
+    // How long each running timer has been going, on the game clock, for the debug overlay
+    pub fn timers(&self) -> Vec<(&'static str, Duration)> {
+        let now = self.game_time;
+        let mut timers = vec![("Drop interval", self.drop_interval()), ("Since drop", now.saturating_sub(self.last_drop_time))];
+        if let Some(started) = self.lock_timer {
+            timers.push(("Lock delay", now.saturating_sub(started)));
+        }
+        if let Some(started) = self.lock_flash_started {
+            timers.push(("Lock flash", now.saturating_sub(started)));
+        }
+        if self.is_clearing() {
+            timers.push(("Line clear", now.saturating_sub(self.clear_started)));
+        }
+        if self.is_collapsing() {
+            timers.push(("Collapse", now.saturating_sub(self.collapse_started)));
+        }
+        timers
+    }
+
+    // How far to shift the board's contents this frame for the line clear shake, in cells. The
+    // offset jitters without drawing on the piece generator, so replays stay in step, and it
+    // never exceeds the shake's strength.
+    pub fn shake_offset(&self) -> (f32, f32) {
+        let Some(shake) = self.shake else {
+            return (0.0, 0.0);
+        };
+        let age = self.game_time.saturating_sub(shake.started).as_secs_f32();
+        let remaining = 1.0 - age / SHAKE_DURATION.as_secs_f32();
+        if remaining <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let size = shake.strength * remaining;
+        (size * (age * 97.0).sin(), size * (age * 83.0).cos())
+    }
+
+    // Play time so far, excluding pauses and the starting countdown
+    pub fn elapsed(&self) -> Duration {
+        self.game_time.saturating_sub(self.config.countdown)
+    }
+
+    // Whole seconds left before play starts, rounded up, or None once it has
+    pub fn countdown(&self) -> Option<u64> {
+        let remaining = self.config.countdown.saturating_sub(self.game_time);
+        (!remaining.is_zero()).then(|| remaining.as_millis().div_ceil(1000) as u64)
+    }
+
+    // Short name of a piece type, such as "T"
+    pub fn piece_name(&self, block_type: i32) -> &str {
+        self.pieces.get(block_type as usize).map_or("", |piece| piece.name.as_str())
+    }
+
+    // Color of the frozen block at a grid position, or None if it is empty or off the grid
+    pub fn cell(&self, x: i32, y: i32) -> Option<Color> {
+        if !(0..self.width).contains(&x) || !(0..self.height).contains(&y) {
+            return None;
+        }
+        self.grid[y as usize][x as usize].map(|cell| cell.color)
+    }
+
+    // Height of the stack in a column: rows from the floor up to its highest frozen block
+    pub fn column_height(&self, x: i32) -> i32 {
+        (0..self.height).find(|&y| self.cell(x, y).is_some()).map_or(0, |top| self.height - top)
+    }
+
+    // Empty cells with a frozen block somewhere above them in the same column
+    pub fn hole_count(&self) -> i32 {
+        (0..self.width)
+            .map(|x| {
+                let top = self.height - self.column_height(x);
+                (top..self.height).filter(|&y| self.cell(x, y).is_none()).count() as i32
+            })
+            .sum()
+    }
+
+    // Sum of every column's height
+    pub fn aggregate_height(&self) -> i32 {
+        (0..self.width).map(|x| self.column_height(x)).sum()
+    }
+
+    // Cells of a piece type in its spawn orientation, offset from the top-left of its bounds,
+    // and its color, for drawing it away from the grid
+    pub fn piece_shape(&self, block_type: i32) -> (&[(i32, i32)], Color) {
+        self.pieces.get(block_type as usize).map_or((&[], Color::WHITE), |piece| (piece.cells.as_slice(), piece.color))
+    }
+
+    // Color scheme the game was started with
+    pub fn theme(&self) -> Theme {
+        self.config.theme
+    }
+
+    // Lines needed to win in sprint mode, or None for an endless game
+    pub fn target_lines(&self) -> Option<i32> {
+        self.config.target_lines
+    }
+
+    // Level needed to win in marathon mode, or None for an endless game; a target no higher
+    // than the start level would win on the first lock, so it is at least one level above it
+    pub fn target_level(&self) -> Option<i32> {
+        self.config.target_level.map(|target| target.max(self.config.start_level + 1))
+    }
+
+    // Average pieces locked per minute of play
+    pub fn pieces_per_minute(&self) -> f32 {
+        let minutes = self.elapsed().as_secs_f32() / 60.0;
+        if minutes > 0.0 {
+            self.pieces_placed as f32 / minutes
+        } else {
+            0.0
+        }
+    }
+
+    // Recompute the level from the start level and the lines cleared so far
+    fn update_level(&mut self) {
+        self.level = self.config.start_level + self.total_lines / LINES_PER_LEVEL;
+    }
+
+    // Lines cleared towards the next level, and the lines each level takes
+    pub fn level_progress(&self) -> (i32, i32) {
+        (self.total_lines % LINES_PER_LEVEL, LINES_PER_LEVEL)
+    }
+
+    // Level a new game starts at
+    pub fn start_level(&self) -> i32 {
+        self.config.start_level
+    }
+
+    // Change the start level, taking effect on the current level immediately and on restarts
+    pub fn set_start_level(&mut self, level: i32) {
+        self.config.start_level = level;
+        self.update_level();
+        self.options_changed();
+    }
+
+    // Time between automatic drops at level 1
+    pub fn base_drop_interval(&self) -> Duration {
+        self.base_drop_interval
+    }
+
+    // Change the level 1 drop speed, taking effect immediately and on restarts
+    pub fn set_base_drop_interval(&mut self, interval: Duration) {
+        self.base_drop_interval = interval;
+        self.config.drop_interval = interval;
+        self.options_changed();
+    }
+
+    // Cells per second a held soft drop moves
+    pub fn soft_drop_speed(&self) -> u32 {
+        self.config.soft_drop_speed
+    }
+
+    // Change the soft drop speed, taking effect immediately and on restarts
+    pub fn set_soft_drop_speed(&mut self, speed: u32) {
+        self.config.soft_drop_speed = speed;
+        self.options_changed();
+    }
+
+    // Whether soft dropping onto the stack locks at once or after the lock delay
+    pub fn soft_drop_lock(&self) -> SoftDropLock {
+        self.config.soft_drop_lock
+    }
+
+    // Change how soft drops lock, taking effect immediately and on restarts
+    pub fn set_soft_drop_lock(&mut self, lock: SoftDropLock) {
+        self.config.soft_drop_lock = lock;
+        self.options_changed();
+    }
+
+    // Moves and turns that may restart the lock delay of each piece
+    pub fn lock_reset_limit(&self) -> u32 {
+        self.config.lock_reset_limit
+    }
+
+    // Change the lock reset limit, taking effect immediately and on restarts
+    pub fn set_lock_reset_limit(&mut self, limit: u32) {
+        self.config.lock_reset_limit = limit;
+        self.options_changed();
+    }
+
+    // A replay plays back under the options the game ends with, so a game whose options change
+    // part way through would replay differently and is no longer recorded
+    fn options_changed(&mut self) {
+        self.recording = false;
+    }
+
+    // Time between soft drop steps, independent of the gravity at the current level
+    fn soft_drop_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.config.soft_drop_speed.max(1)
+    }
+
+    // Time between automatic drops at the current level, from the gravity table scaled so
+    // level 1 drops at the base interval; levels beyond the table keep its last entry
+    fn drop_interval(&self) -> Duration {
+        let (Some(first), Some(last)) = (self.gravity.first(), self.gravity.last()) else {
+            return self.base_drop_interval;
+        };
+        let entry = self.gravity.get((self.level - 1).max(0) as usize).unwrap_or(last);
+        let nanos = entry.as_nanos() * self.base_drop_interval.as_nanos() / first.as_nanos().max(1);
+        Duration::from_nanos(nanos as u64)
+    }
+
+    // Start measuring game time from the given real time
+    pub fn start_clock(&mut self, real_now: Duration) {
+        self.last_update = real_now;
+        self.clock_start = real_now;
+    }
+
+    // Write the full game state to a JSON save file
+    pub fn save_game(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| format!("could not encode game: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("could not write {}: {}", path, e))
+    }
+
+    // Restore a game from a JSON save file, resuming its clock at the given real time
+    pub fn load_game(path: &str, real_now: Duration) -> Result<GameState, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        let mut game: GameState =
+            serde_json::from_str(&json).map_err(|e| format!("{} is corrupt: {}", path, e))?;
+
+        // Reject saves whose grid doesn't match its recorded size, which would panic later
+        let width_ok = game.grid.iter().all(|row| row.len() == game.width as usize);
+        let piece_ok = game.tetromino.blocks.iter().all(|block| (0..game.width).contains(&block.x) && block.y < game.height);
+        if game.width < 1 || game.grid.len() != game.height as usize || !width_ok || !piece_ok {
+            return Err(format!("{} is corrupt: grid does not match its size", path));
+        }
+        let kinds = 0..game.pieces.len() as i32;
+        let mut types = game.next_pieces.iter().chain(&game.bag).chain(&game.held_tetromino).chain(&game.forced_next);
+        let counts_ok = game.spawn_counts.len() == game.pieces.len();
+        if !kinds.contains(&game.tetromino.block_type) || !types.all(|block_type| kinds.contains(block_type)) || !counts_ok {
+            return Err(format!("{} is corrupt: unknown piece type", path));
+        }
+
+        // Timers are all on the saved game clock, which carries on from where it stopped
+        game.start_clock(real_now);
+        Ok(game)
+    }
+
+    // Take the events raised since the last call
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // How fast the game clock runs for debugging, 1.0 being real time
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale.unwrap_or(1.0)
+    }
+
+    // Slow down or speed up the game clock for watching timing and animations. A game played
+    // off real time would replay differently, so it is no longer recorded.
+    pub fn set_time_scale(&mut self, scale: f32) {
+        if scale == 1.0 {
+            self.time_scale = None;
+        } else {
+            self.time_scale = Some(scale);
+            self.recording = false;
+        }
+    }
+
+    // Register hooks to call as things happen, replacing any registered before; they stay
+    // registered across restarts
+    pub fn set_observer(&mut self, observer: Box<dyn GameObserver>) {
+        self.observer = Some(observer);
+    }
+
+    // Tell the observer about an event
+    fn notify(&mut self, event: &GameEvent) {
+        let Some(observer) = &mut self.observer else {
+            return;
+        };
+        match *event {
+            GameEvent::PieceLocked => observer.on_lock(),
+            GameEvent::LinesCleared(lines) => observer.on_line_clear(lines),
+            GameEvent::LevelUp(level) => observer.on_level_up(level),
+            GameEvent::GameOver => observer.on_game_over(),
+            _ => {}
+        }
+    }
+
+    // Raise an event for the observer and the frontend
+    fn emit(&mut self, event: GameEvent) {
+        self.notify(&event);
+        self.events.push(event);
+    }
+
+    // Advance the game by one frame, given the time since start and the player's input
+    pub fn update(&mut self, real_now: Duration, input: &Input) {
+        if self.game_over {
+            // Restart is only available from the game over screen
+            if input.pressed.contains(&Action::Restart) {
+                let (observer, time_scale) = (self.observer.take(), self.time_scale);
+                *self = GameState::new(self.config.clone());
+                self.observer = observer;
+                self.time_scale = time_scale;
+                self.start_clock(real_now);
+            }
+            return;
+        }
+
+        if self.recording {
+            self.replay.push(ReplayFrame { time: real_now - self.clock_start, input: input.clone() });
+        }
+
+        // The game clock only advances by time spent unpaused, so nothing catches up after
+        // a pause
+        if !self.paused {
+            let delta = real_now.saturating_sub(self.last_update);
+            self.game_time += self.time_scale.map_or(delta, |scale| Duration::from_nanos((delta.as_nanos() as f64 * scale as f64) as u64));
+        }
+        self.last_update = real_now;
+        if input.pressed.contains(&Action::Pause) {
+            self.paused = !self.paused;
+        }
+        if self.paused {
+            return;
+        }
+        if let Some(block_type) = input.forced_piece {
+            if self.practice && (0..self.pieces.len() as i32).contains(&block_type) {
+                self.forced_next = Some(block_type);
+            }
+        }
+
+        let now = self.game_time;
+        self.update_particles();
+        self.update_popups();
+
+        // Nothing moves until the countdown ends, though hold swaps the first piece for the
+        // next; gravity then starts a full interval later
+        if self.countdown().is_some() {
+            if input.pressed.contains(&Action::Hold) {
+                self.swap_opening_piece();
+            }
+            self.last_drop_time = now;
+            return;
+        }
+
+        // Gravity, input and the next spawn wait while a locking piece flashes
+        if let Some(started) = self.lock_flash_started {
+            if now - started >= LOCK_FLASH {
+                self.lock_flash_started = None;
+                self.freeze_tetromino();
+            }
+            return;
+        }
+
+        // Gravity and input wait while completed rows flash
+        if self.is_clearing() {
+            if now - self.clear_started >= CLEAR_ANIMATION {
+                self.finish_lock();
+            }
+            return;
+        }
+
+        // Then the rows above fall into place; gravity gives the new piece a full interval
+        // once they land
+        if self.is_collapsing() {
+            if now - self.collapse_started < COLLAPSE_ANIMATION {
+                return;
+            }
+            self.collapse_drops.clear();
+            self.last_drop_time = now;
+        }
+
+        // Handle left/right movement: a press moves once, holding repeats after the DAS delay
+        let direction = match (input.held.contains(&Action::MoveLeft), input.held.contains(&Action::MoveRight)) {
+            (true, false) => -1,
+            (false, true) => 1,
+            _ => 0,
+        };
+        if direction != self.shift_direction {
+            self.shift_direction = direction;
+            self.shift_started = now;
+            if direction != 0 {
+                self.move_tetromino(direction, 0);
+                self.last_move_time = now;
+            }
+        } else if direction != 0
+            && now - self.shift_started >= self.das_delay
+            && now - self.last_move_time >= self.arr_rate
+        {
+            self.move_tetromino(direction, 0);
+            self.last_move_time = now;
+        }
+
+        // Steer toward the target column one step per frame while no direction key is held
+        if let (0, Some(target)) = (direction, input.target_column) {
+            let (min_x, _, max_x, _) = self.tetromino.bounding_box();
+            let center = (min_x + max_x) / 2;
+            if target != center {
+                self.move_tetromino((target - center).signum(), 0);
+            }
+        }
+
+        // Handle fast drop on its own timer so it doesn't delay horizontal repeats
+        if input.held.contains(&Action::SoftDrop) && now - self.last_soft_drop_time >= self.soft_drop_interval() {
+            self.soft_drop();
+            self.last_soft_drop_time = now;
+        }
+
+        // Handle hard drop
+        if input.pressed.contains(&Action::HardDrop) {
+            self.hard_drop();
+            // The piece has landed and is flashing, so nothing else may move it this frame
+            return;
+        }
+
+        // Handle hold
+        if input.pressed.contains(&Action::Hold) {
+            self.hold_piece();
+        }
+
+        // Handle rotation
+        if input.pressed.contains(&Action::RotateCw) {
+            self.rotate_tetromino();
+        }
+        if input.pressed.contains(&Action::RotateCcw) {
+            self.rotate_tetromino_ccw();
+        }
+
+        // Handle automatic dropping; at 20G the piece falls as far as it can every update, so it
+        // is always resting and only lock delay leaves time to slide it
+        if self.twenty_g {
+            let dy = self.landing_offset();
+            if dy > 0 {
+                self.tetromino.translate(0, dy);
+                self.tspin = false;
+            }
+            self.start_lock_timer();
+            self.last_drop_time = now;
+        } else if self.puzzle {
+            // Nothing falls on its own; a piece only locks once it is dropped onto the stack
+            self.last_drop_time = now;
+        } else if now - self.last_drop_time >= self.drop_interval() {
+            // A piece that can't fall any further starts its lock delay
+            if !self.move_tetromino(0, 1) {
+                self.start_lock_timer();
+            }
+            self.last_drop_time = now;
+        }
+
+        // Lock the piece once it has rested long enough
+        self.update_lock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(tetromino: &Tetromino) -> Vec<(i32, i32)> {
+        tetromino.cells().collect()
+    }
+
+    #[test]
+    fn four_rotations_return_each_piece_to_its_cells() {
+        let mut game = GameState::new(GameConfig::default());
+        for block_type in 0..7 {
+            // Start away from the walls so no kicks are needed
+            game.tetromino = game.create_tetromino(block_type);
+            game.tetromino.translate(0, 5);
+            let original = cells(&game.tetromino);
+
+            for _ in 0..4 {
+                game.rotate_tetromino();
+            }
+            assert_eq!(cells(&game.tetromino), original, "clockwise, type {}", block_type);
+            assert_eq!(game.tetromino.rotation(), 0);
+
+            for _ in 0..4 {
+                game.rotate_tetromino_ccw();
+            }
+            assert_eq!(cells(&game.tetromino), original, "counter-clockwise, type {}", block_type);
+            assert_eq!(game.tetromino.rotation(), 0);
+        }
+    }
+
+    #[test]
+    fn piece_shapes_start_at_the_top_left_of_their_bounds() {
+        let game = GameState::new(GameConfig::default());
+        assert_eq!(game.piece_shape(0).0, &[(0, 0), (1, 0), (2, 0), (3, 0)]);
+        for block_type in 0..7 {
+            let (cells, color) = game.piece_shape(block_type);
+            assert_eq!(cells.iter().map(|&(x, _)| x).min(), Some(0));
+            assert_eq!(cells.iter().map(|&(_, y)| y).min(), Some(0));
+            assert!(color == game.create_tetromino(block_type).blocks[0].color);
+        }
+        assert!(game.piece_shape(7).0.is_empty());
+    }
+
+    #[test]
+    fn occupancy_queries_measure_heights_and_holes() {
+        let mut game = game_with_rows(&[
+            "..#.......",
+            ".#.#......",
+            "##.#....#.",
+            "#..######.",
+        ]);
+        game.grid[game.height as usize - 4][2] = Some(Cell { color: Color::RED, block_type: Some(4) });
+        let bottom = game.height - 1;
+        assert!(game.cell(2, bottom - 3) == Some(Color::RED));
+        assert!(game.cell(0, bottom) == Some(Color::WHITE));
+        assert!(game.cell(1, bottom).is_none());
+        assert!(game.cell(-1, bottom).is_none() && game.cell(0, game.height).is_none());
+
+        let heights: Vec<i32> = (0..10).map(|x| game.column_height(x)).collect();
+        assert_eq!(heights, vec![2, 3, 4, 3, 1, 1, 1, 1, 2, 0]);
+        assert_eq!(game.aggregate_height(), 18);
+        // One under column 1's blocks and three under the overhang in column 2
+        assert_eq!(game.hole_count(), 4);
+
+        let empty = GameState::new(GameConfig::default());
+        assert_eq!((empty.aggregate_height(), empty.hole_count()), (0, 0));
+    }
+
+    #[test]
+    fn translated_moves_a_copy_and_leaves_the_piece_alone() {
+        let game = GameState::new(GameConfig::default());
+        let original = cells(&game.tetromino);
+        let moved = game.tetromino.translated(2, 3);
+        assert_eq!(cells(&game.tetromino), original);
+        let expected: Vec<(i32, i32)> = original.iter().map(|&(x, y)| (x + 2, y + 3)).collect();
+        assert_eq!(cells(&moved), expected);
+
+        // The pivot moves too, so the copy turns in place just as the original would
+        assert_eq!(cells(&moved.rotated(true)), cells(&game.tetromino.rotated(true).translated(2, 3)));
+    }
+
+    #[test]
+    fn bounding_box_covers_every_cell() {
+        let color = Color::WHITE;
+        let blocks = [(1, 2), (1, 3), (1, 4), (2, 4)].iter().map(|&(x, y)| Block { x, y, color }).collect();
+        let piece = Tetromino { blocks, block_type: 2, rotation: 0, pivot: (3, 7) };
+        assert_eq!(piece.cells().collect::<Vec<_>>(), vec![(1, 2), (1, 3), (1, 4), (2, 4)]);
+        assert_eq!(piece.bounding_box(), (1, 2, 2, 4));
+
+        // The I piece lies flat four wide, then stands four tall after a turn
+        let mut game = GameState::new(GameConfig::default());
+        game.tetromino = game.create_tetromino(0);
+        let (min_x, min_y, max_x, max_y) = game.tetromino.bounding_box();
+        assert_eq!((max_x - min_x, max_y - min_y), (3, 0));
+        let (min_x, min_y, max_x, max_y) = game.tetromino.rotated(true).bounding_box();
+        assert_eq!((max_x - min_x, max_y - min_y), (0, 3));
+    }
+
+    #[test]
+    fn pieces_loaded_from_a_file_spawn_and_rotate_generically() {
+        let pieces = crate::pieces::load("pentominoes.pieces").unwrap();
+        assert_eq!(pieces.len(), 12);
+        let config = GameConfig { pieces: Some(pieces), ..GameConfig::default() };
+        let mut game = GameState::new(config);
+        assert_eq!(game.tetromino.blocks.len(), 5);
+
+        for block_type in 0..12 {
+            game.tetromino = game.create_tetromino(block_type);
+            game.tetromino.translate(0, 8);
+            let original = cells(&game.tetromino);
+            for turn in 1..=4 {
+                game.rotate(true);
+                assert_eq!(game.tetromino.rotation(), turn % 4, "type {}", block_type);
+                assert!(game.can_move(0, 0), "type {}", block_type);
+            }
+            assert_eq!(cells(&game.tetromino), original, "type {}", block_type);
+        }
+    }
+
+    #[test]
+    fn i_piece_rotates_in_place() {
+        let mut game = GameState::new(GameConfig::default());
+        game.tetromino = game.create_tetromino(0);
+        game.tetromino.translate(0, 5);
+
+        game.rotate_tetromino();
+        assert_eq!(cells(&game.tetromino), vec![(5, 4), (5, 5), (5, 6), (5, 7)]);
+        game.rotate_tetromino();
+        assert_eq!(cells(&game.tetromino), vec![(6, 6), (5, 6), (4, 6), (3, 6)]);
+    }
+
+    #[test]
+    fn i_piece_rotates_upright_one_row_above_the_floor() {
+        for clockwise in [true, false] {
+            let mut game = GameState::new(GameConfig::default());
+            game.tetromino = game.create_tetromino(0);
+            game.tetromino.translate(0, game.height - 2);
+
+            // Turning in place would stick two cells through the floor, so a kick lifts it
+            game.rotate(clockwise);
+            assert_eq!(game.tetromino.rotation(), if clockwise { 1 } else { 3 });
+            assert!(game.tetromino.blocks.iter().all(|block| block.y < game.height));
+            assert!(game.can_move(0, 0));
+
+            // And back down flat once it has dropped onto the floor
+            let dy = game.landing_offset();
+            game.tetromino.translate(0, dy);
+            game.rotate(clockwise);
+            assert_eq!(game.tetromino.rotation(), 2);
+            assert!(game.can_move(0, 0));
+        }
+    }
+
+    // Rotate every piece from every orientation both ways after place() has positioned it on
+    // an empty grid, checking each turn kicks into a legal spot in the new orientation
+    fn check_kicks(place: fn(&mut GameState)) {
+        for block_type in 0..7 {
+            for turns in 0..4 {
+                for clockwise in [true, false] {
+                    let mut game = GameState::new(GameConfig::default());
+                    game.tetromino = game.create_tetromino(block_type);
+                    game.tetromino.translate(0, 8);
+                    for _ in 0..turns {
+                        game.rotate(true);
+                    }
+                    place(&mut game);
+                    let before = cells(&game.tetromino);
+                    let rotation = game.tetromino.rotation();
+
+                    game.rotate(clockwise);
+                    let label = format!("type {}, state {}, clockwise {}", block_type, rotation, clockwise);
+                    if block_type == 1 {
+                        assert_eq!(cells(&game.tetromino), before, "{}", label);
+                        continue;
+                    }
+                    let expected = if clockwise { (rotation + 1) % 4 } else { (rotation + 3) % 4 };
+                    assert_eq!(game.tetromino.rotation(), expected, "{}", label);
+                    assert!(game.can_move(0, 0), "{}", label);
+                    assert!(game.tetromino.blocks.iter().all(|block| block.y >= 0), "{}", label);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn valid_positions_stay_inside_the_walls_above_the_floor_and_off_filled_cells() {
+        let game = game_with_rows(&["#........."]);
+        let bottom = game.height - 1;
+        assert!(game.is_valid_position(&[(0, 0), (9, 0), (1, bottom), (9, bottom)]));
+        assert!(game.is_valid_position(&[]));
+
+        // Walls and floor
+        assert!(!game.is_valid_position(&[(-1, 0)]));
+        assert!(!game.is_valid_position(&[(10, 0)]));
+        assert!(!game.is_valid_position(&[(5, bottom + 1)]));
+
+        // A filled cell spoils the whole placement
+        assert!(!game.is_valid_position(&[(1, bottom), (0, bottom)]));
+
+        // Above the top is free, but only between the walls
+        assert!(game.is_valid_position(&[(0, -1), (9, -3)]));
+        assert!(!game.is_valid_position(&[(-1, -1)]));
+    }
+
+    #[test]
+    fn rotating_flush_against_the_left_wall_kicks_inside() {
+        check_kicks(|game| {
+            while game.can_move(-1, 0) {
+                game.tetromino.translate(-1, 0);
+            }
+        });
+    }
+
+    #[test]
+    fn rotating_flush_against_the_right_wall_kicks_inside() {
+        check_kicks(|game| {
+            while game.can_move(1, 0) {
+                game.tetromino.translate(1, 0);
+            }
+        });
+    }
+
+    #[test]
+    fn rotating_on_the_floor_kicks_inside() {
+        check_kicks(|game| {
+            let dy = game.landing_offset();
+            game.tetromino.translate(0, dy);
+        });
+    }
+
+    #[test]
+    fn rotating_against_a_filled_column_kicks_away_from_it() {
+        let mut game = GameState::new(GameConfig::default());
+        for row in game.grid.iter_mut() {
+            row[0] = Some(Cell { color: Color::WHITE, block_type: None });
+        }
+        // An upright T pointing right, with its stem in column 1 beside the filled column
+        game.tetromino = game.create_tetromino(4);
+        game.tetromino.translate(0, 8);
+        game.rotate(true);
+        while game.can_move(-1, 0) {
+            game.tetromino.translate(-1, 0);
+        }
+        assert!(game.tetromino.blocks.iter().all(|block| block.x >= 1));
+
+        // Flattening it would reach into column 0, so the first kick shifts it right instead
+        game.rotate(true);
+        assert_eq!(game.tetromino.rotation(), 2);
+        assert!(game.can_move(0, 0));
+        let columns: Vec<i32> = game.tetromino.blocks.iter().map(|block| block.x).collect();
+        assert_eq!(columns.iter().min(), Some(&1));
+        assert_eq!(columns.iter().max(), Some(&3));
+    }
+
+    #[test]
+    fn spawning_onto_the_stack_ends_the_game() {
+        let mut game = GameState::new(GameConfig::default());
+        // Fill every row but the top, leaving one column open so nothing clears
+        for row in game.grid.iter_mut().skip(1) {
+            for cell in row.iter_mut().skip(1) {
+                *cell = Some(Cell { color: Color::WHITE, block_type: None });
+            }
+        }
+        // Lock a flat I piece into the empty top row against the left wall
+        game.tetromino = game.create_tetromino(0);
+        game.tetromino.translate(-3, 0);
+        assert!(game.can_move(0, 0));
+
+        game.freeze_tetromino();
+        assert!(game.game_over);
+        assert!(game.drain_events().contains(&GameEvent::GameOver));
+    }
+
+    #[test]
+    fn topping_out_leaves_no_partial_piece() {
+        let mut game = GameState::new(GameConfig::default());
+        // An L piece with its top row above the grid and the rest in row 0, ordered so the
+        // in-bounds blocks come before the one above the grid
+        game.tetromino = game.create_tetromino(2);
+        game.tetromino.translate(0, -1);
+        game.tetromino.blocks.reverse();
+
+        game.freeze_tetromino();
+        assert!(game.game_over);
+        assert!(game.grid.iter().all(|row| row.iter().all(|cell| cell.is_none())));
+    }
+
+    // Build a board from rows of '#' (filled) and '.' (empty), aligned to the bottom of the grid
+    fn game_with_rows(rows: &[&str]) -> GameState {
+        let mut game = GameState::new(GameConfig::default());
+        let top = game.height as usize - rows.len();
+        for (i, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == '#' {
+                    game.grid[top + i][x] = Some(Cell { color: Color::WHITE, block_type: None });
+                }
+            }
+        }
+        game
+    }
+
+    // The bottom rows of the grid in the same notation as game_with_rows
+    fn bottom_rows(game: &GameState, count: usize) -> Vec<String> {
+        game.grid[game.height as usize - count..]
+            .iter()
+            .map(|row| row.iter().map(|cell| if cell.is_some() { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn clearing_a_single_bottom_row() {
+        let mut game = game_with_rows(&[
+            "#.........",
+            "##########",
+        ]);
+        game.clear_lines();
+        assert_eq!(bottom_rows(&game, 2), vec!["..........", "#........."]);
+        assert_eq!(game.score, 100);
+        assert_eq!(game.total_lines, 1);
+    }
+
+    #[test]
+    fn clears_score_their_table_value_times_the_level() {
+        let mut game = game_with_rows(&[
+            "#.........",
+            "##########",
+            "##########",
+        ]);
+        game.level = 3;
+        game.scoring.lines[1] = 250;
+        game.clear_lines();
+        assert_eq!(game.score, 750);
+        let clear = game.last_clear.unwrap();
+        assert_eq!((clear.lines, clear.base, clear.level, clear.total()), (2, 250, 3, 750));
+    }
+
+    #[test]
+    fn frozen_cells_keep_their_piece_type_through_a_clear() {
+        let mut game = game_with_rows(&[
+            "#######...",
+            "########..",
+        ]);
+        game.tetromino = game.create_tetromino(1);
+        game.tetromino.translate(4, 0);
+        let dy = game.landing_offset();
+        game.tetromino.translate(0, dy);
+        game.freeze_tetromino();
+        game.finish_lock();
+
+        let bottom = &game.grid[game.height as usize - 1];
+        assert_eq!(bottom_rows(&game, 1), vec!["#######.##"]);
+        assert_eq!(bottom[8].map(|cell| cell.block_type), Some(Some(1)));
+        assert_eq!(bottom[9].map(|cell| cell.color), Some(game.pieces[1].color));
+        assert_eq!(bottom[0].map(|cell| cell.block_type), Some(None));
+    }
+
+    #[test]
+    fn clearing_two_non_adjacent_rows() {
+        let mut game = game_with_rows(&[
+            "..#.......",
+            "##########",
+            "#.#.#.#.#.",
+            "##########",
+            ".#.#.#.#.#",
+        ]);
+        game.clear_lines();
+        assert_eq!(
+            bottom_rows(&game, 5),
+            vec!["..........", "..........", "..#.......", "#.#.#.#.#.", ".#.#.#.#.#"]
+        );
+        assert_eq!(game.score, 300);
+        assert_eq!(game.total_lines, 2);
+    }
+
+    #[test]
+    fn clearing_four_stacked_rows() {
+        let mut game = game_with_rows(&[
+            "...##.....",
+            "##########",
+            "##########",
+            "##########",
+            "##########",
+        ]);
+        game.clear_lines();
+        assert_eq!(bottom_rows(&game, 5), vec!["..........", "..........", "..........", "..........", "...##....."]);
+        assert_eq!(game.score, 800);
+        assert_eq!(game.total_lines, 4);
+    }
+
+    #[test]
+    fn clearing_a_full_row_below_empty_rows_leaves_an_empty_grid() {
+        let mut game = game_with_rows(&["##########"]);
+        game.clear_lines();
+        assert!(game.grid.iter().all(|row| row.iter().all(|cell| cell.is_none())));
+        // The single plus the perfect clear bonus
+        assert_eq!(game.score, 3100);
+        assert!(game.drain_events().contains(&GameEvent::PerfectClear));
+    }
+
+    #[test]
+    fn an_empty_grid_without_a_clear_is_not_a_perfect_clear() {
+        let mut game = GameState::new(GameConfig::default());
+        game.clear_lines();
+        assert_eq!(game.score, 0);
+        assert!(!game.drain_events().contains(&GameEvent::PerfectClear));
+    }
+
+    #[test]
+    fn move_tetromino_reports_blocked_moves_without_locking() {
+        let mut game = game_with_rows(&["##########"]);
+        let dy = game.landing_offset();
+        assert!(game.move_tetromino(0, dy));
+        let landed = cells(&game.tetromino);
+
+        assert!(!game.move_tetromino(0, 1));
+        assert_eq!(cells(&game.tetromino), landed);
+        assert!(game.lock_timer.is_none());
+        assert!(game.move_tetromino(-1, 0));
+    }
+
+    #[test]
+    fn reaching_the_marathon_target_level_wins() {
+        let mut game = game_with_rows(&["##########"]);
+        game.config.target_level = Some(3);
+        game.total_lines = 18;
+        game.finish_lock();
+        assert!(!game.won);
+
+        game.grid[game.height as usize - 1] = vec![Some(Cell { color: Color::WHITE, block_type: None }); 10];
+        game.grid[game.height as usize - 2][0] = Some(Cell { color: Color::WHITE, block_type: None });
+        game.finish_lock();
+        assert_eq!(game.level, 3);
+        assert!(game.won && game.game_over);
+    }
+
+    #[test]
+    fn a_marathon_target_below_the_start_level_still_takes_a_level_up() {
+        let mut game = game_with_rows(&["#........."]);
+        game.config.target_level = Some(3);
+        game.set_start_level(8);
+        assert_eq!(game.target_level(), Some(9));
+        game.finish_lock();
+        assert!(!game.won && !game.game_over);
+    }
+
+    #[test]
+    fn soft_drop_moves_at_its_own_speed_and_scores_each_cell() {
+        let mut game = GameState::new(GameConfig { soft_drop_speed: 20, start_level: 5, countdown: Duration::ZERO, ..GameConfig::default() });
+        let top = game.tetromino.blocks[0].y;
+        let input = Input { held: HashSet::from([Action::SoftDrop]), ..Input::default() };
+
+        // 20 cells per second is a step every 50ms, whatever the level's gravity
+        for ms in (0..=200).step_by(10) {
+            game.update(Duration::from_millis(ms), &input);
+        }
+        assert_eq!(game.tetromino.blocks[0].y - top, 4);
+        assert_eq!(game.score, 4);
+    }
+
+    #[test]
+    fn soft_drop_onto_the_stack_locks_at_once_only_when_set_to_instant() {
+        let mut game = game_with_rows(&["##########"]);
+        let dy = game.landing_offset();
+        game.tetromino.translate(0, dy);
+        game.soft_drop();
+        assert!(game.lock_timer.is_some() && !game.is_lock_flashing());
+
+        let mut game = game_with_rows(&["##########"]);
+        game.set_soft_drop_lock(SoftDropLock::Instant);
+        let dy = game.landing_offset();
+        game.tetromino.translate(0, dy);
+        game.soft_drop();
+        assert!(game.lock_timer.is_none() && game.is_lock_flashing());
+    }
+
+    #[test]
+    fn a_resting_piece_locks_once_it_runs_out_of_lock_resets() {
+        let mut game = game_with_rows(&["##########"]);
+        game.set_lock_reset_limit(3);
+        let dy = game.landing_offset();
+        game.tetromino.translate(0, dy);
+        game.start_lock_timer();
+        for _ in 0..3 {
+            game.reset_lock_timer();
+            assert!(game.lock_timer.is_some() && !game.is_lock_flashing());
+        }
+        game.reset_lock_timer();
+        assert!(game.is_lock_flashing());
+
+        // The next piece gets a fresh allowance
+        game.finish_lock();
+        assert_eq!(game.lock_resets, 0);
+    }
+
+    #[test]
+    fn lock_delay_and_auto_repeat_come_from_the_config_and_survive_a_restart() {
+        let config = GameConfig {
+            lock_delay: Duration::from_millis(300),
+            das_delay: Duration::from_millis(120),
+            arr_rate: Duration::from_millis(25),
+            ..GameConfig::default()
+        };
+        let mut game = GameState::new(config);
+        game.end_game();
+        game.update(Duration::ZERO, &Input { pressed: HashSet::from([Action::Restart]), ..Input::default() });
+        assert!(!game.game_over);
+        assert_eq!(game.lock_delay, Duration::from_millis(300));
+        assert_eq!(game.das_delay, Duration::from_millis(120));
+        assert_eq!(game.arr_rate, Duration::from_millis(25));
+    }
+
+    #[test]
+    fn puzzle_mode_pieces_stay_put_until_dropped() {
+        let mut game = GameState::new(GameConfig { puzzle: true, countdown: Duration::ZERO, ..GameConfig::default() });
+        let start = cells(&game.tetromino);
+        for seconds in 0..=10 {
+            game.update(Duration::from_secs(seconds), &Input::default());
+        }
+        assert_eq!(cells(&game.tetromino), start);
+
+        let input = Input { held: HashSet::from([Action::SoftDrop]), ..Input::default() };
+        game.update(Duration::from_millis(10_100), &input);
+        assert_eq!(game.tetromino.blocks[0].y, start[0].1 + 1);
+    }
+
+    #[test]
+    fn twenty_g_drops_pieces_to_the_floor_at_once_and_into_gaps_they_slide_over() {
+        let mut game = game_with_rows(&["###.######"]);
+        game.config.countdown = Duration::ZERO;
+        game.twenty_g = true;
+        game.tetromino = game.create_tetromino(0);
+        game.update(Duration::ZERO, &Input::default());
+        assert_eq!(game.landing_offset(), 0);
+        let resting = cells(&game.tetromino);
+        assert!(resting.iter().all(|&(_, y)| y == game.height - 2));
+        assert!(game.lock_timer.is_some());
+
+        // Turned upright and slid over the gap, the I piece drops straight into it
+        game.rotate_tetromino();
+        game.update(Duration::from_millis(10), &Input::default());
+        while game.tetromino.blocks[0].x > 3 {
+            game.move_tetromino(-1, 0);
+            game.update(Duration::from_millis(20), &Input::default());
+        }
+        assert_eq!(game.tetromino.cells().map(|(_, y)| y).max(), Some(game.height - 1));
+
+        // Nothing else holds the piece up, so it locks once the lock delay runs out
+        for ms in (30..=700).step_by(10) {
+            game.update(Duration::from_millis(ms), &Input::default());
+        }
+        assert_eq!(game.pieces_placed, 1);
+    }
+
+    #[test]
+    fn soft_drop_does_not_delay_horizontal_repeats() {
+        let mut game = GameState::new(GameConfig { width: 30, countdown: Duration::ZERO, ..GameConfig::default() });
+        let start_x = game.tetromino.blocks[0].x;
+        let input = Input {
+            held: [Action::MoveLeft, Action::SoftDrop].into_iter().collect(),
+            ..Input::default()
+        };
+
+        // Holding Left moves at 0ms, then repeats every ARR step once DAS expires at 170ms
+        for ms in (0..=400).step_by(10) {
+            game.update(Duration::from_millis(ms), &input);
+        }
+        assert_eq!(start_x - game.tetromino.blocks[0].x, 6);
+    }
+
+    #[test]
+    fn nothing_moves_until_the_countdown_ends() {
+        let mut game = GameState::new(GameConfig::default());
+        game.start_clock(Duration::ZERO);
+        let start = cells(&game.tetromino);
+        let input = Input {
+            held: HashSet::from([Action::MoveLeft, Action::SoftDrop]),
+            pressed: HashSet::from([Action::RotateCw, Action::HardDrop]),
+            ..Input::default()
+        };
+
+        for ms in (0..3000).step_by(100) {
+            game.update(Duration::from_millis(ms), &input);
+            assert_eq!(game.countdown(), Some(3 - ms / 1000));
+        }
+        assert_eq!(cells(&game.tetromino), start);
+        assert_eq!(game.pieces_placed, 0);
+
+        // Gravity takes its first step a drop interval after the countdown
+        game.update(Duration::from_millis(3000), &Input::default());
+        assert_eq!(game.countdown(), None);
+        game.update(Duration::from_millis(3000) + DROP_INTERVAL, &Input::default());
+        assert_eq!(cells(&game.tetromino), start.iter().map(|&(x, y)| (x, y + 1)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn time_spent_paused_does_not_count_towards_gravity() {
+        let mut game = GameState::new(GameConfig { countdown: Duration::ZERO, ..GameConfig::default() });
+        game.start_clock(Duration::ZERO);
+        let pause = Input { pressed: HashSet::from([Action::Pause]), ..Input::default() };
+        let top = game.tetromino.blocks.iter().map(|block| block.y).min();
+
+        game.update(Duration::from_millis(100), &Input::default());
+        game.update(Duration::from_millis(200), &pause);
+        game.update(Duration::from_secs(60), &pause);
+        game.update(Duration::from_millis(60_100), &Input::default());
+        assert_eq!(game.elapsed(), Duration::from_millis(300));
+        assert_eq!(game.tetromino.blocks.iter().map(|block| block.y).min(), top);
+    }
+
+    #[test]
+    fn garbage_rows_have_one_varying_gap_and_clear_normally() {
+        let mut game = GameState::new(GameConfig { garbage_rows: 5, ..GameConfig::default() });
+        let gaps: Vec<usize> = bottom_rows(&game, 5)
+            .iter()
+            .map(|row| {
+                assert_eq!(row.matches('.').count(), 1, "row {}", row);
+                row.find('.').unwrap()
+            })
+            .collect();
+        assert!(gaps.windows(2).all(|pair| pair[0] != pair[1]));
+        assert!(bottom_rows(&game, 6)[0].chars().all(|c| c == '.'));
+
+        // Plugging the bottom gap clears that row like any other
+        let bottom = game.height as usize - 1;
+        game.grid[bottom][gaps[4]] = Some(Cell { color: Color::WHITE, block_type: None });
+        game.clear_lines();
+        assert_eq!(game.score, 100);
+        assert_eq!(game.total_lines, 1);
+    }
+
+    #[test]
+    fn replaying_recorded_input_reproduces_the_game() {
+        let mut game = GameState::new(GameConfig { seed: Some(7), countdown: Duration::ZERO, ..GameConfig::default() });
+        game.start_clock(Duration::ZERO);
+        // Shift, rotate and hard drop a few pieces, one action every 300ms
+        for step in 0..18u64 {
+            let action = match step % 3 {
+                0 if step % 2 == 0 => Action::MoveLeft,
+                0 => Action::MoveRight,
+                1 => Action::RotateCw,
+                _ => Action::HardDrop,
+            };
+            let input = Input {
+                held: [action].into_iter().filter(|action| action.is_continuous()).collect(),
+                pressed: [action].into_iter().filter(|action| !action.is_continuous()).collect(),
+                ..Input::default()
+            };
+            game.update(Duration::from_millis(step * 300), &input);
+            game.update(Duration::from_millis(step * 300 + 150), &Input::default());
+        }
+        assert_eq!(game.pieces_placed, 6);
+        assert!(!game.game_over);
+
+        let replay = Replay {
+            config: GameConfig { seed: Some(game.seed), ..game.config.clone() },
+            frames: std::mem::take(&mut game.replay),
+        };
+        let mut replayed = GameState::from_replay(&replay);
+        replayed.start_clock(Duration::from_secs(100));
+        for frame in &replay.frames {
+            replayed.update(Duration::from_secs(100) + frame.time, &frame.input);
+        }
+        assert_eq!(replayed.score, game.score);
+        assert_eq!(replayed.pieces_placed, game.pieces_placed);
+        assert_eq!(replayed.grid, game.grid);
+    }
+
+    #[test]
+    fn spawn_counts_stay_within_one_of_each_other() {
+        let mut game = GameState::new(GameConfig { seed: Some(3), ..GameConfig::default() });
+        for dealt in 2..=50 {
+            game.tetromino = game.take_next_piece();
+            let counts = &game.spawn_counts;
+            assert_eq!(counts.iter().sum::<u32>(), dealt);
+            assert!(counts.iter().max().unwrap() - counts.iter().min().unwrap() <= 1, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn the_same_seed_deals_the_same_bags() {
+        let deal = |seed| {
+            let mut game = GameState::new(GameConfig { seed: Some(seed), ..GameConfig::default() });
+            let mut types = vec![game.tetromino.block_type];
+            for _ in 0..13 {
+                types.push(game.take_next_piece().block_type);
+            }
+            types
+        };
+        let types = deal(42);
+        assert_eq!(types, deal(42));
+        for bag in types.chunks(7) {
+            let mut bag = bag.to_vec();
+            bag.sort_unstable();
+            assert_eq!(bag, (0..7).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn line_clears_show_their_points_rising_from_the_cleared_rows() {
+        let mut game = GameState::new(GameConfig { countdown: Duration::ZERO, ..GameConfig::default() });
+        game.start_clock(Duration::ZERO);
+        let filled = Some(Cell { color: Color::WHITE, block_type: None });
+        let (bottom, top) = (game.height as usize - 1, 0);
+        game.grid[bottom] = vec![filled; game.width as usize];
+        game.grid[bottom - 1][0] = filled;
+        game.clear_lines();
+        assert_eq!(game.popups.len(), 1);
+        assert_eq!(game.popups[0].text, "+100");
+        assert_eq!(game.popups[0].y, bottom as f32 + 0.5);
+
+        // It floats up as it fades, then disappears
+        game.update(POPUP_LIFETIME / 2, &Input::default());
+        assert!(game.popups[0].y < bottom as f32 + 0.5);
+        assert!(game.popups[0].life < 1.0);
+        game.update(POPUP_LIFETIME, &Input::default());
+        assert!(game.popups.is_empty());
+
+        // A clear at the very top starts low enough to stay below the score header
+        game.grid[top] = vec![filled; game.width as usize];
+        game.clear_lines();
+        game.update(POPUP_LIFETIME * 2 - Duration::from_millis(1), &Input::default());
+        assert!(game.popups[0].y >= POPUP_TOP);
+    }
+
+    #[test]
+    fn drop_interval_follows_the_gravity_table_scaled_to_the_base_interval() {
+        let gravity = [800, 400, 100].map(Duration::from_millis).to_vec();
+        let mut game = GameState::new(GameConfig { gravity, ..GameConfig::default() });
+        game.set_base_drop_interval(Duration::from_millis(400));
+        let intervals: Vec<Duration> = (1..=5)
+            .map(|level| {
+                game.level = level;
+                game.drop_interval()
+            })
+            .collect();
+        assert_eq!(intervals, [400, 200, 50, 50, 50].map(Duration::from_millis));
+
+        // The default classic curve starts at the default drop interval
+        let mut game = GameState::new(GameConfig::default());
+        assert_eq!(game.drop_interval(), DROP_INTERVAL);
+        game.level = 30;
+        assert_eq!(game.drop_interval(), DROP_INTERVAL / 48);
+    }
+
+    #[test]
+    fn practice_mode_spawns_the_picked_piece_next_without_disturbing_the_queue() {
+        let config = GameConfig { practice: true, countdown: Duration::ZERO, ..GameConfig::default() };
+        let mut game = GameState::new(config.clone());
+        let queue = game.next_pieces.clone();
+        let pick = Input { forced_piece: Some(4), ..Input::default() };
+        game.update(Duration::ZERO, &pick);
+        assert_eq!(game.upcoming()[0], 4);
+        assert_eq!(game.upcoming().len(), NEXT_PIECES);
+
+        let drop = Input { pressed: HashSet::from([Action::HardDrop]), ..Input::default() };
+        game.update(Duration::from_millis(10), &drop);
+        for ms in (20..=500).step_by(10) {
+            game.update(Duration::from_millis(ms), &Input::default());
+        }
+        assert_eq!(game.pieces_placed, 1);
+        assert_eq!(game.tetromino.block_type, 4);
+        assert_eq!(game.next_pieces, queue);
+        assert!(game.forced_next.is_none());
+
+        // Outside practice mode the pick is ignored
+        let mut game = GameState::new(GameConfig { practice: false, ..config });
+        game.update(Duration::ZERO, &pick);
+        assert!(game.forced_next.is_none());
+    }
+
+    #[test]
+    fn clearing_split_rows_keeps_the_rows_between_in_order() {
+        let mut game = game_with_rows(&["#.........", "##########", "..#.......", "##########", ".........#"]);
+        game.clear_lines();
+        assert_eq!(game.grid.len(), game.height as usize);
+        assert_eq!(bottom_rows(&game, 4), vec!["..........", "#.........", "..#.......", ".........#"]);
+        assert_eq!(game.total_lines, 2);
+        assert_eq!(game.score, 300);
+    }
+
+    #[test]
+    fn clearing_every_row_including_the_top_one_leaves_an_empty_grid() {
+        let rows = vec!["##########"; 20];
+        let mut game = game_with_rows(&rows);
+        game.clear_lines();
+        assert_eq!(game.grid.len(), game.height as usize);
+        assert!(game.grid.iter().all(|row| row.len() == 10 && row.iter().all(Option::is_none)));
+        // Scored as a tetris plus a perfect clear
+        assert_eq!(game.total_lines, 20);
+        assert_eq!(game.score, 800 + 3000);
+    }
+
+    #[test]
+    fn rows_above_a_clear_fall_into_place_before_play_carries_on() {
+        let mut game = game_with_rows(&["#.........", "##########", ".#........", "##########"]);
+        game.config.countdown = Duration::ZERO;
+        game.finish_lock();
+        assert!(game.is_collapsing());
+        assert_eq!(bottom_rows(&game, 2), vec!["#.........", ".#........"]);
+        let bottom = game.height as usize - 1;
+        // Each row starts as high as it was and falls by the full rows that were below it
+        assert_eq!(game.collapse_offset(bottom - 1), 2.0);
+        assert_eq!(game.collapse_offset(bottom), 1.0);
+
+        // The new piece can't move until the rows land
+        let x = game.tetromino.blocks[0].x;
+        let left = Input { held: HashSet::from([Action::MoveLeft]), ..Input::default() };
+        game.update(Duration::ZERO, &left);
+        game.update(Duration::from_millis(100), &left);
+        assert_eq!(game.tetromino.blocks[0].x, x);
+        assert!(game.collapse_offset(bottom - 1) > 0.0 && game.collapse_offset(bottom - 1) < 2.0);
+
+        game.update(Duration::from_millis(160), &left);
+        assert!(!game.is_collapsing());
+        assert_eq!(game.collapse_offset(bottom - 1), 0.0);
+        assert_eq!(game.tetromino.blocks[0].x, x - 1);
+    }
+
+    #[test]
+    fn the_observer_hears_locks_clears_level_ups_and_the_end_of_the_game() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+        impl GameObserver for Recorder {
+            fn on_lock(&mut self) {
+                self.0.borrow_mut().push("lock".to_string());
+            }
+            fn on_line_clear(&mut self, lines: i32) {
+                self.0.borrow_mut().push(format!("clear {}", lines));
+            }
+            fn on_level_up(&mut self, level: i32) {
+                self.0.borrow_mut().push(format!("level {}", level));
+            }
+            fn on_game_over(&mut self) {
+                self.0.borrow_mut().push("game over".to_string());
+            }
+        }
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut game = game_with_rows(&["#########."]);
+        game.set_observer(Box::new(Recorder(Rc::clone(&calls))));
+        game.total_lines = 9;
+        // An upright I piece in the gap on the right clears the bottom row
+        game.tetromino = game.create_tetromino(0).rotated(true);
+        let (_, _, right, _) = game.tetromino.bounding_box();
+        game.tetromino.translate(9 - right, 0);
+        let dy = game.landing_offset();
+        game.tetromino.translate(0, dy);
+        game.freeze_tetromino();
+        game.finish_lock();
+        assert_eq!(*calls.borrow(), vec!["lock", "clear 1", "level 2"]);
+        // The frontend still gets the same events
+        assert!(game.drain_events().contains(&GameEvent::LevelUp(2)));
+
+        // The observer stays registered through a restart
+        game.end_game();
+        game.update(Duration::ZERO, &Input { pressed: HashSet::from([Action::Restart]), ..Input::default() });
+        game.end_game();
+        assert_eq!(calls.borrow()[3..], ["game over", "game over"]);
+    }
+
+    #[test]
+    fn changing_an_option_mid_game_stops_recording_until_the_next_game() {
+        let mut game = GameState::new(GameConfig::default());
+        game.set_soft_drop_speed(20);
+        assert!(!game.recording);
+
+        game.end_game();
+        game.update(Duration::ZERO, &Input { pressed: HashSet::from([Action::Restart]), ..Input::default() });
+        assert!(game.recording);
+        assert_eq!(game.soft_drop_speed(), 20);
+    }
+
+    #[test]
+    fn the_debug_time_scale_slows_the_game_clock_and_stops_recording() {
+        let mut game = GameState::new(GameConfig::default());
+        game.update(Duration::ZERO, &Input::default());
+        game.set_time_scale(0.25);
+        game.update(Duration::from_millis(400), &Input::default());
+        assert_eq!(game.game_time, Duration::from_millis(100));
+        assert!(!game.recording);
+
+        game.set_time_scale(4.0);
+        game.update(Duration::from_millis(500), &Input::default());
+        assert_eq!(game.game_time, Duration::from_millis(500));
+        game.set_time_scale(1.0);
+        assert_eq!(game.time_scale(), 1.0);
+    }
+
+    #[test]
+    fn hold_during_the_countdown_swaps_the_first_piece_once_and_keeps_hold_free() {
+        let mut game = GameState::new(GameConfig::default());
+        let (first, next) = (game.tetromino.block_type, game.next_pieces[0]);
+        let hold = Input { pressed: HashSet::from([Action::Hold]), ..Input::default() };
+        game.update(Duration::ZERO, &hold);
+        assert_eq!((game.tetromino.block_type, game.next_pieces[0]), (next, first));
+        assert!(game.held_tetromino.is_none() && !game.hold_used);
+        assert_eq!(game.spawn_counts.iter().sum::<u32>(), 1);
+        assert_eq!(game.spawn_counts[next as usize], 1);
+
+        // Only once
+        game.update(Duration::from_millis(100), &hold);
+        assert_eq!(game.tetromino.block_type, next);
+
+        // After the countdown the same press is a normal hold
+        game.update(Duration::from_secs(3), &Input::default());
+        game.update(Duration::from_millis(3010), &hold);
+        assert_eq!(game.held_tetromino, Some(next));
+        assert!(game.hold_used);
+    }
+
+    #[test]
+    fn demo_player_survives_and_clears_lines() {
+        let mut game = GameState::new(GameConfig { seed: Some(7), countdown: Duration::ZERO, ..GameConfig::default() });
+        game.start_clock(Duration::ZERO);
+        let mut demo = crate::ai::Demo::default();
+        let step = Duration::from_secs(1) / 120;
+        let mut now = Duration::ZERO;
+        for _ in 0..200_000 {
+            if game.pieces_placed >= 100 || game.game_over {
+                break;
+            }
+            now += step;
+            let input = demo.input(&game);
+            game.update(now, &input);
+        }
+        assert!(!game.game_over);
+        assert!(game.pieces_placed >= 100);
+        assert!(game.total_lines >= 20, "cleared only {} lines", game.total_lines);
+    }
+
+    #[test]
+    fn pieces_spawn_partly_in_the_hidden_rows_and_follow_the_spawn_offset() {
+        let game = GameState::new(GameConfig { hidden_rows: 2, ..GameConfig::default() });
+        assert_eq!(game.grid.len(), 22);
+        let top = game.tetromino.blocks.iter().map(|block| block.y).min();
+        assert_eq!(top, Some(1));
+
+        let plain = GameState::new(GameConfig { seed: Some(3), ..GameConfig::default() });
+        let nudged = GameState::new(GameConfig { seed: Some(3), spawn_offset: (1, -1), ..GameConfig::default() });
+        let shifted: Vec<(i32, i32)> = cells(&plain.tetromino).iter().map(|&(x, y)| (x + 1, y - 1)).collect();
+        assert_eq!(cells(&nudged.tetromino), shifted);
+    }
+
+    #[test]
+    fn rotating_into_the_rows_above_the_grid_still_respects_filled_cells() {
+        let mut game = GameState::new(GameConfig::default());
+        game.tetromino = game.create_tetromino(0);
+        let overlaps = |game: &GameState| {
+            game.tetromino.blocks.iter().any(|block| block.y >= 0 && game.grid[block.y as usize][block.x as usize].is_some())
+        };
+
+        // With room to turn, the I piece pokes one cell above the grid, as movement allows
+        game.rotate(true);
+        assert_eq!(cells(&game.tetromino), [(5, -1), (5, 0), (5, 1), (5, 2)]);
+        game.rotate(false);
+
+        // A filled cell in row 0 of that column blocks the plain turn, and no kick may overlap it
+        game.grid[0][5] = Some(Cell { color: Color::WHITE, block_type: None });
+        game.rotate(true);
+        assert!(!overlaps(&game));
+        while game.move_tetromino(0, 1) {
+            assert!(!overlaps(&game));
+        }
+    }
+
+    #[test]
+    fn topping_out_in_zen_mode_clears_the_bottom_half_and_carries_on() {
+        let mut game = game_with_rows(&["#########."; 20]);
+        game.zen = true;
+        game.score = 500;
+        game.finish_lock();
+        assert!(!game.game_over);
+        assert!(game.can_move(0, 0));
+        assert_eq!(game.score, 500);
+        assert!(game.grid[..10].iter().all(|row| row.iter().all(|cell| cell.is_none())));
+        assert!(game.grid[10..].iter().all(|row| row[0].is_some()));
+        assert!(game.drain_events().contains(&GameEvent::StackReset));
+
+        // Without zen mode the same stack ends the game
+        let mut game = game_with_rows(&["#########."; 20]);
+        game.finish_lock();
+        assert!(game.game_over);
+    }
+
+    #[test]
+    fn holding_onto_a_stack_over_the_spawn_tops_out() {
+        let mut game = game_with_rows(&["#########."; 20]);
+        game.hold_piece();
+        assert!(game.game_over);
+
+        let mut game = game_with_rows(&["#########."; 20]);
+        game.zen = true;
+        game.hold_piece();
+        assert!(!game.game_over);
+        assert!(game.drain_events().contains(&GameEvent::StackReset));
+    }
+
+    #[test]
+    fn a_piece_locking_above_the_top_in_zen_mode_falls_into_the_cleared_space() {
+        let mut game = game_with_rows(&["#########."; 20]);
+        game.zen = true;
+        game.config.countdown = Duration::ZERO;
+        game.tetromino = game.create_tetromino(0);
+        let top = game.tetromino.bounding_box().1;
+        game.tetromino.translate(0, -1 - top);
+        let above = cells(&game.tetromino);
+        game.freeze_tetromino();
+        assert!(!game.game_over);
+        assert_eq!(cells(&game.tetromino), above);
+        assert!(game.lock_timer.is_none() && !game.is_lock_flashing());
+        assert_eq!(game.pieces_placed, 0);
+
+        // It drops on through the emptied top half and lands on what is left of the stack
+        for ms in (0..=10_000).step_by(10) {
+            game.update(Duration::from_millis(ms), &Input::default());
+        }
+        assert!(!game.game_over);
+        assert!(game.pieces_placed >= 1);
+        assert!(game.grid[9].iter().any(|cell| cell.is_some()));
+    }
+
+    #[test]
+    fn line_clears_shake_the_board_harder_for_more_lines_then_settle() {
+        let mut game = game_with_rows(&["##########", "##########", "##########", "##########", "#........."]);
+        assert_eq!(game.shake_offset(), (0.0, 0.0));
+        game.clear_lines();
+        let strength = game.shake.map(|shake| shake.strength).unwrap();
+        assert!(strength > SHAKE_PER_LINE * 4.0 && strength <= 0.3 + f32::EPSILON);
+
+        // The offset stays within the strength as it dies away, then stops
+        for ms in (10..250).step_by(20) {
+            game.game_time = Duration::from_millis(ms);
+            let (x, y) = game.shake_offset();
+            assert!(x.abs() <= strength && y.abs() <= strength);
+        }
+        game.game_time = SHAKE_DURATION;
+        assert_eq!(game.shake_offset(), (0.0, 0.0));
+    }
+}
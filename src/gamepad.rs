@@ -0,0 +1,43 @@
+// Fixed controller mapping onto game actions, used alongside the keyboard
+use ggez::event::{Axis, Button};
+use ggez::Context;
+
+use crate::game::{Action, Input};
+
+const STICK_THRESHOLD: f32 = 0.5; // How far the left stick must be pushed to count as a direction
+
+// The one-shot action a controller button triggers, if any. Start is handled separately since
+// it also confirms on the menus.
+pub fn button_action(button: Button) -> Option<Action> {
+    match button {
+        Button::South => Some(Action::RotateCw),
+        Button::East => Some(Action::RotateCcw),
+        Button::North | Button::DPadUp => Some(Action::HardDrop),
+        Button::LeftTrigger | Button::RightTrigger => Some(Action::Hold),
+        Button::Select => Some(Action::Restart),
+        _ => None,
+    }
+}
+
+// Add the continuous actions held on any connected controller, from the d-pad or left stick
+pub fn read_held(ctx: &Context, input: &mut Input) {
+    for (_, pad) in ctx.gamepad.gamepads() {
+        let stick_x = pad.value(Axis::LeftStickX);
+        let stick_y = pad.value(Axis::LeftStickY);
+        if pad.is_pressed(Button::DPadLeft) || stick_x < -STICK_THRESHOLD {
+            input.held.insert(Action::MoveLeft);
+        }
+        if pad.is_pressed(Button::DPadRight) || stick_x > STICK_THRESHOLD {
+            input.held.insert(Action::MoveRight);
+        }
+        // Stick y points up
+        if pad.is_pressed(Button::DPadDown) || stick_y < -STICK_THRESHOLD {
+            input.held.insert(Action::SoftDrop);
+        }
+    }
+}
+
+// Number of controllers currently connected
+pub fn connected(ctx: &Context) -> usize {
+    ctx.gamepad.gamepads().count()
+}
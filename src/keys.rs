@@ -0,0 +1,156 @@
+// Keyboard bindings from game actions to physical keys, loaded from the config file
+use ggez::input::keyboard::KeyCode;
+
+use crate::game::Action;
+
+// The key bound to each game action
+pub struct KeyBindings {
+    pub move_left: KeyCode,
+    pub move_right: KeyCode,
+    pub soft_drop: KeyCode,
+    pub hard_drop: KeyCode,
+    pub rotate_cw: KeyCode,
+    pub rotate_ccw: KeyCode,
+    pub hold: KeyCode,
+    pub pause: KeyCode,
+    pub restart: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            move_left: KeyCode::Left,
+            move_right: KeyCode::Right,
+            soft_drop: KeyCode::Down,
+            hard_drop: KeyCode::Space,
+            rotate_cw: KeyCode::Up,
+            rotate_ccw: KeyCode::Z,
+            hold: KeyCode::C,
+            pause: KeyCode::P,
+            restart: KeyCode::R,
+        }
+    }
+}
+
+impl KeyBindings {
+    // Bind an action, both given by their config file names
+    pub fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+        let key = parse_key(key).ok_or_else(|| format!("unknown key '{}'", key))?;
+        let slot = match name {
+            "move_left" => &mut self.move_left,
+            "move_right" => &mut self.move_right,
+            "soft_drop" => &mut self.soft_drop,
+            "hard_drop" => &mut self.hard_drop,
+            "rotate_cw" => &mut self.rotate_cw,
+            "rotate_ccw" => &mut self.rotate_ccw,
+            "hold" => &mut self.hold,
+            "pause" => &mut self.pause,
+            "restart" => &mut self.restart,
+            _ => return Err(format!("unknown action '{}'", name)),
+        };
+        *slot = key;
+        Ok(())
+    }
+
+    // The action bound to a key, if any
+    pub fn action_for(&self, key: KeyCode) -> Option<Action> {
+        self.pairs().into_iter().find(|&(bound, _)| bound == key).map(|(_, action)| action)
+    }
+
+    // Every binding as a (key, action) pair
+    pub fn pairs(&self) -> [(KeyCode, Action); 9] {
+        [
+            (self.move_left, Action::MoveLeft),
+            (self.move_right, Action::MoveRight),
+            (self.soft_drop, Action::SoftDrop),
+            (self.hard_drop, Action::HardDrop),
+            (self.rotate_cw, Action::RotateCw),
+            (self.rotate_ccw, Action::RotateCcw),
+            (self.hold, Action::Hold),
+            (self.pause, Action::Pause),
+            (self.restart, Action::Restart),
+        ]
+    }
+}
+
+// Config file names of the keys that can be bound
+const KEY_NAMES: [(&str, KeyCode); 53] = [
+    ("A", KeyCode::A),
+    ("B", KeyCode::B),
+    ("C", KeyCode::C),
+    ("D", KeyCode::D),
+    ("E", KeyCode::E),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("I", KeyCode::I),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("M", KeyCode::M),
+    ("N", KeyCode::N),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("Q", KeyCode::Q),
+    ("R", KeyCode::R),
+    ("S", KeyCode::S),
+    ("T", KeyCode::T),
+    ("U", KeyCode::U),
+    ("V", KeyCode::V),
+    ("W", KeyCode::W),
+    ("X", KeyCode::X),
+    ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z),
+    ("0", KeyCode::Key0),
+    ("1", KeyCode::Key1),
+    ("2", KeyCode::Key2),
+    ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4),
+    ("5", KeyCode::Key5),
+    ("6", KeyCode::Key6),
+    ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8),
+    ("9", KeyCode::Key9),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Space", KeyCode::Space),
+    ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Back),
+    ("LShift", KeyCode::LShift),
+    ("RShift", KeyCode::RShift),
+    ("LControl", KeyCode::LControl),
+    ("RControl", KeyCode::RControl),
+    ("LAlt", KeyCode::LAlt),
+    ("RAlt", KeyCode::RAlt),
+    ("Comma", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    ("Slash", KeyCode::Slash),
+    ("Semicolon", KeyCode::Semicolon),
+];
+
+// Look up a key by the name used in the config file
+fn parse_key(name: &str) -> Option<KeyCode> {
+    KEY_NAMES.iter().find(|&&(key_name, _)| key_name == name).map(|&(_, key)| key)
+}
+
+// The config file name of a key, for showing bindings to the player
+pub fn key_name(key: KeyCode) -> &'static str {
+    KEY_NAMES.iter().find(|&&(_, bound)| bound == key).map_or("?", |&(name, _)| name)
+}
+
+// What an action does, as shown in the controls help
+pub fn describe(action: Action) -> &'static str {
+    match action {
+        Action::MoveLeft => "Move left",
+        Action::MoveRight => "Move right",
+        Action::SoftDrop => "Soft drop",
+        Action::HardDrop => "Hard drop",
+        Action::RotateCw => "Rotate clockwise",
+        Action::RotateCcw => "Rotate counter-clockwise",
+        Action::Hold => "Hold",
+        Action::Pause => "Pause",
+        Action::Restart => "Restart",
+    }
+}
@@ -0,0 +1,62 @@
+// The ten best scores with the names of the players who set them, kept between runs
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+pub const LEADERBOARD_FILE: &str = "highscores.json"; // Leaderboard file in the working directory
+pub const MAX_NAME_LEN: usize = 10; // Longest name a player can enter
+const MAX_ENTRIES: usize = 10; // Scores kept on the table
+
+// One score on the table
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    pub score: i32,
+}
+
+// Scores sorted best first; among equal scores the earliest stays ahead
+#[derive(Default, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub entries: Vec<Entry>,
+}
+
+impl Leaderboard {
+    // Read the table from disk, starting empty if the file is missing or malformed
+    pub fn load(path: &str) -> Leaderboard {
+        let mut board: Leaderboard = fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        // A hand-edited file may be out of order or too long
+        board.entries.sort_by_key(|entry| std::cmp::Reverse(entry.score));
+        board.entries.truncate(MAX_ENTRIES);
+        board
+    }
+
+    // Write the table back to disk
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| e.to_string())
+    }
+
+    // The best score on the table, or 0 if it is empty
+    pub fn best(&self) -> i32 {
+        self.entries.first().map_or(0, |entry| entry.score)
+    }
+
+    // Whether a score would make the table; tying the lowest score on a full table isn't enough
+    pub fn qualifies(&self, score: i32) -> bool {
+        score > 0 && (self.entries.len() < MAX_ENTRIES || self.entries.last().is_some_and(|entry| score > entry.score))
+    }
+
+    // Add a score below any equal ones and return its place on the table, dropping the
+    // lowest score if the table overflows
+    pub fn insert(&mut self, name: &str, score: i32) -> Option<usize> {
+        if !self.qualifies(score) {
+            return None;
+        }
+        let rank = self.entries.iter().position(|entry| entry.score < score).unwrap_or(self.entries.len());
+        self.entries.insert(rank, Entry { name: name.to_string(), score });
+        self.entries.truncate(MAX_ENTRIES);
+        Some(rank)
+    }
+}
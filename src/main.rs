@@ -1,319 +1,1720 @@
 // Import required dependencies
-use ggez::{Context, GameResult, graphics::{self, DrawParam, Color, Canvas, Text}, event, input::keyboard::KeyCode, timer};
-use rand::Rng;
+use ggez::{Context, GameResult, conf::FullscreenType, graphics::{self, DrawParam, Color, Text, TextFragment}, event::{self, Button, GamepadId}, input::{keyboard::{KeyCode, KeyInput}, mouse::MouseButton}};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
 use std::time::Duration;
 
-// Game constants
-const BLOCK_SIZE: f32 = 25.0;
-const GRID_WIDTH: i32 = 10;
-const GRID_HEIGHT: i32 = 20;
-const SCREEN_WIDTH: f32 = BLOCK_SIZE * GRID_WIDTH as f32;
-const SCREEN_HEIGHT: f32 = BLOCK_SIZE * GRID_HEIGHT as f32;
-const MOVE_INTERVAL: Duration = Duration::from_millis(100); // Minimum time between moves
-const DROP_INTERVAL: Duration = Duration::from_millis(500); // Time between automatic drops
-
-// Represents a single block in the game
-#[derive(Clone, Copy)]
-struct Block {
-    x: i32,
-    y: i32,
-    color: Color,
+mod ai;
+mod cli;
+mod config;
+mod game;
+mod gamepad;
+mod keys;
+mod leaderboard;
+mod pieces;
+mod sound;
+
+use game::{Action, ClearScore, GameConfig, GameEvent, GameObserver, GameState, Input, Replay, ReplayFrame, SoftDropLock, NEXT_PIECES};
+use ai::Demo;
+use config::{Config, Difficulty};
+use keys::KeyBindings;
+use leaderboard::{Leaderboard, LEADERBOARD_FILE};
+use sound::{Music, Sounds};
+
+const SAVE_FILE: &str = "savegame.json"; // Save game file in the working directory
+const NOTICE_DURATION: Duration = Duration::from_secs(2); // How long status messages stay up
+const BANNER_DURATION: Duration = Duration::from_millis(1500); // How long gameplay banners stay up
+const DROP_SETTING_STEP: Duration = Duration::from_millis(50); // Drop interval change per settings keypress
+const SOFT_DROP_SETTING_STEP: u32 = 5; // Soft drop speed change per settings keypress, in cells per second
+const VOLUME_SETTING_STEP: u32 = 10; // Music volume change per settings keypress, in percent
+const SETTINGS_ROWS: usize = 9; // Lines in the settings overlay
+const LOGIC_RATE: u32 = 120; // Game logic steps per second, whatever the display refresh rate
+
+// Rendering constants
+const BASE_BLOCK_SIZE: f32 = 25.0; // Block size the layout offsets below are designed for
+const TEXT_SIZE: f32 = 16.0; // Text height at the base block size
+const PREVIEW_SPACING: f32 = 3.0; // Vertical distance between queued pieces, in cells
+const MIN_BLOCK_SIZE: f32 = 4.0; // Smallest block size a resized window shrinks to
+const SCREEN_FILL: f32 = 0.9; // Share of the monitor a board too big for it is shrunk to fill
+const DANGER_ROWS: usize = 2; // Top rows that glow red while the stack reaches into them
+const HUD_COLUMNS: i32 = 6; // Width of the side panel's score column in cells
+const COUNT_COLUMNS: i32 = 3; // Width of the side panel's piece count column in cells
+const COUNT_SPACING: f32 = 22.0; // Distance between piece count rows at the base block size
+const HUD_MARGIN: f32 = 10.0; // Gap around the side panel's contents at the base block size
+const HUD_LINE: f32 = 20.0; // Height of a line of side panel text at the base block size
+
+impl From<game::Color> for Color {
+    fn from(color: game::Color) -> Self {
+        Color::new(color.r, color.g, color.b, color.a)
+    }
+}
+
+// Which screen the frontend is showing
+#[derive(Clone, Copy, PartialEq)]
+enum Screen {
+    Menu,
+    Playing,
+    GameOver,
+    Won,
+}
+
+// A replay being fed into the game in place of live input
+struct Playback {
+    frames: Vec<ReplayFrame>,
+    next: usize,     // Index of the next frame to apply
+    start: Duration, // Time the playback started, which frame times are measured from
+}
+
+// Totals over every game played since launch, shown on the menu
+#[derive(Default)]
+struct SessionStats {
+    games: u32, // Games that have ended
+    lines: i32, // Lines cleared across them all
 }
 
-// Represents a complete tetromino (a game piece made up of 4 blocks)
-struct Tetromino {
-    blocks: Vec<Block>,
-    block_type: i32, // Used to identify the shape type (0-6)
+// Keeps the session totals up to date from a game's observer hooks
+struct SessionObserver(Rc<RefCell<SessionStats>>);
+
+impl GameObserver for SessionObserver {
+    fn on_line_clear(&mut self, lines: i32) {
+        self.0.borrow_mut().lines += lines;
+    }
+
+    fn on_game_over(&mut self) {
+        self.0.borrow_mut().games += 1;
+    }
 }
 
-// Main game state structure
-struct GameState {
-    tetromino: Tetromino,      // Current falling piece
-    grid: Vec<Vec<Option<Color>>>, // Game grid: None = empty, Some(Color) = filled
-    game_over: bool,           // Game over flag
-    score: i32,                // Current score
-    last_move_time: Duration,  // Time of last movement
-    last_drop_time: Duration,  // Time of last automatic drop
-    last_rotate_time: Duration, // Time of last rotation
+// ggez adapter: forwards input to the game logic and renders its state
+struct MainState {
+    config: GameConfig,
+    screen: Screen,
+    game: Option<GameState>,    // Only constructed once the player starts from the menu
+    notice: Option<(String, Duration)>, // Status message and the time it disappears
+    banner: Option<(String, Duration)>, // Gameplay callout over the playfield and the time it disappears
+    breakdown: Option<(String, Duration)>, // Itemized points of the last line clear and the time it disappears
+    block_size: f32,            // Side of one grid cell in pixels
+    block_mesh: graphics::Mesh, // Bordered block shared by every drawn cell, tinted per draw
+    pattern_meshes: Vec<Option<graphics::Mesh>>, // Overlay marking each piece type when patterns are on; None is plain
+    sounds: Sounds,
+    music: Music,
+    settings: Config,           // Key bindings and settings from the config file
+    settings_open: bool,        // Settings overlay is showing over the pause screen
+    settings_row: usize,        // Selected settings line: 0 = start level, 1 = drop interval, 2 = soft drop speed, 3 = soft drop lock, 4 = shake, 5 = patterns, 6 = lock resets, 7 = cell highlight, 8 = music volume
+    help_open: bool,            // Controls help is showing, over the menu or a paused game
+    quit_prompt: Option<bool>,  // "Quit?" is showing over a game in progress; true if asking paused the game
+    quit_confirmed: bool,       // The player answered yes, so the next quit goes through
+    debug_open: bool,           // Frame rate and game internals are showing in a corner
+    debug_keys: bool,           // --debug: F6, F7 and F8 set the game clock to 0.25x, 1x and 4x speed
+    mouse_column: Option<i32>,  // Playfield column under the mouse while it is steering
+    pressed: HashSet<Action>,   // Key presses and mouse clicks waiting for the next update
+    forced_piece: Option<i32>,  // Practice mode: piece type picked with a number key, waiting for the next update
+    pending_replay: Option<Replay>, // Replay from the command line, started on the first update
+    playback: Option<Playback>, // Replay currently driving the game instead of the player
+    demo: Option<Demo>,         // Computer player for demo mode, driving every game instead of the player
+    announced_seed: Option<u64>, // Seed of the game last reported on stdout
+    clock: Duration,            // Game logic time, advanced a fixed step at a time
+    pad_start: bool,            // Start was pressed on a controller since the last update
+    gamepads: usize,            // Controllers connected as of the last update
+    preview_width: i32,         // Side panel width in cells
+    offset: (f32, f32),         // Top-left of the board and panel, centered in a resized window
+    fitted_grid: (i32, i32),    // Visible grid size the block size and offset were last fitted to
+    leaderboard: Leaderboard,   // Best scores with names, shown on the menu and end screens
+    name_entry: Option<String>, // Name being typed for a score that made the leaderboard
+    new_rank: Option<usize>,    // Place of the most recently entered score, highlighted on the table
+    session: Rc<RefCell<SessionStats>>, // Totals over the games played live since launch
 }
 
-impl GameState {
-    // Initialize a new game state
-    fn new() -> Self {
-        let grid = vec![vec![None; GRID_WIDTH as usize]; GRID_HEIGHT as usize];
-        let tetromino = Self::create_random_tetromino();
-        
-        GameState {
-            tetromino,
-            grid,
-            game_over: false,
-            score: 0,
-            last_move_time: Duration::ZERO,
-            last_drop_time: Duration::ZERO,
-            last_rotate_time: Duration::ZERO,
-        }
-    }
-
-    // Create a new random tetromino piece
-    fn create_random_tetromino() -> Tetromino {
-        let mut rng = rand::thread_rng();
-        let block_type = rng.gen_range(0..7);
-        let (blocks, color) = match block_type {
-            0 => (// I-shape
-                vec![(3,0), (4,0), (5,0), (6,0)],
-                Color::CYAN),
-            1 => (// Square
-                vec![(4,0), (5,0), (4,1), (5,1)],
-                Color::YELLOW),
-            2 => (// L-shape
-                vec![(3,0), (3,1), (4,1), (5,1)],
-                Color::RED),
-            3 => (// J-shape
-                vec![(5,0), (3,1), (4,1), (5,1)],
-                Color::GREEN),
-            4 => (// T-shape
-                vec![(4,0), (3,1), (4,1), (5,1)],
-                Color::MAGENTA),
-            5 => (// S-shape
-                vec![(4,0), (5,0), (3,1), (4,1)],
-                Color::WHITE),
-            _ => (// Z-shape
-                vec![(3,0), (4,0), (4,1), (5,1)],
-                Color::new(1.0, 0.5, 0.0, 1.0)), // Orange
+impl MainState {
+    fn new(
+        ctx: &mut Context,
+        config: GameConfig,
+        settings: Config,
+        block_size: f32,
+        preview_width: i32,
+        replay: Option<Replay>,
+        demo: bool,
+    ) -> GameResult<Self> {
+        let block_mesh = build_block_mesh(ctx, block_size)?;
+        let pattern_meshes = build_pattern_meshes(ctx, block_size)?;
+        let sounds = Sounds::load(ctx);
+        let music = Music::load(ctx, settings.muted, settings.music_volume);
+        let fitted_grid = (config.width, config.height);
+        Ok(MainState {
+            config,
+            screen: Screen::Menu,
+            game: None,
+            notice: None,
+            banner: None,
+            breakdown: None,
+            block_size,
+            block_mesh,
+            pattern_meshes,
+            sounds,
+            music,
+            settings,
+            settings_open: false,
+            settings_row: 0,
+            help_open: false,
+            quit_prompt: None,
+            quit_confirmed: false,
+            debug_open: false,
+            debug_keys: false,
+            mouse_column: None,
+            pressed: HashSet::new(),
+            forced_piece: None,
+            pending_replay: replay,
+            playback: None,
+            demo: demo.then(Demo::default),
+            announced_seed: None,
+            clock: Duration::ZERO,
+            pad_start: false,
+            gamepads: 0,
+            preview_width,
+            offset: (0.0, 0.0),
+            fitted_grid,
+            leaderboard: Leaderboard::load(LEADERBOARD_FILE),
+            name_entry: None,
+            new_rank: None,
+            session: Rc::default(),
+        })
+    }
+
+    // Put the finished game's score on the leaderboard under the name typed so far
+    fn submit_name(&mut self, now: Duration) {
+        let (Some(name), Some(game)) = (self.name_entry.take(), &self.game) else {
+            return;
         };
-        
-        Tetromino {
-            blocks: blocks.into_iter()
-                        .map(|(x, y)| Block { x, y, color })
-                        .collect(),
-            block_type,
+        let name = if name.trim().is_empty() { "Player" } else { name.trim() };
+        self.new_rank = self.leaderboard.insert(name, game.score);
+        if let Err(e) = self.leaderboard.save(LEADERBOARD_FILE) {
+            eprintln!("Failed to save leaderboard: {}", e);
+            self.notice = Some(("Leaderboard could not be saved".to_string(), now + NOTICE_DURATION));
         }
     }
 
-    // Rotate the current tetromino 90 degrees clockwise
-    fn rotate_tetromino(&mut self) {
-        if self.tetromino.block_type == 1 { // Square doesn't need rotation
-            return;
+    // Scale factor from the base layout to the chosen block size
+    fn unit(&self) -> f32 {
+        self.block_size / BASE_BLOCK_SIZE
+    }
+
+    // Visible columns and rows of the board on show: the active game's, which a replay or save
+    // may have set to something else, or the configured grid on the menu
+    fn grid_size(&self) -> (i32, i32) {
+        match &self.game {
+            Some(game) => (game.width, game.height - game.hidden_rows),
+            None => (self.config.width, self.config.height),
         }
+    }
 
-        let center = self.tetromino.blocks[1]; // Use second block as rotation center
-        let mut new_blocks = Vec::new();
+    // Size in pixels of the board plus side panel at the current block size
+    fn layout_size(&self) -> (f32, f32) {
+        let (width, height) = self.grid_size();
+        let columns = layout_columns(width, self.preview_width);
+        (columns as f32 * self.block_size, height as f32 * self.block_size)
+    }
+}
 
-        for block in &self.tetromino.blocks {
-            // Calculate new position after rotation
-            let dx = block.x - center.x;
-            let dy = block.y - center.y;
-            let new_x = center.x - dy;
-            let new_y = center.y + dx;
+// Poll the continuous actions whose keys or controller buttons are held down this frame
+fn read_input(ctx: &Context, keys: &KeyBindings) -> Input {
+    let mut input = Input::default();
+    for (key, action) in keys.pairs() {
+        if action.is_continuous() && ctx.keyboard.is_key_pressed(key) {
+            input.held.insert(action);
+        }
+    }
+    gamepad::read_held(ctx, &mut input);
+    input
+}
 
-            // Check if rotation is valid
-            if new_x < 0 || new_x >= GRID_WIDTH || new_y >= GRID_HEIGHT {
-                return;
+// Implement the game loop handlers
+impl event::EventHandler<ggez::GameError> for MainState {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        let now = ctx.time.time_since_start();
+        // Run the game logic in fixed steps that catch up with real time, so gravity and
+        // movement behave the same at any frame rate
+        let step = Duration::from_secs(1) / LOGIC_RATE;
+        let first_step = self.clock;
+        let mut steps = 0;
+        while ctx.time.check_update_time(LOGIC_RATE) {
+            self.clock += step;
+            steps += 1;
+        }
+        // The quit prompt holds every shortcut until it is answered; the game only steps to
+        // take the pause that asking queued, so nothing moves under the prompt
+        if self.quit_prompt.is_some() {
+            if let (Some(game), true) = (&mut self.game, steps > 0) {
+                let mut input = Input { pressed: std::mem::take(&mut self.pressed), ..Input::default() };
+                for i in 1..=steps {
+                    game.update(first_step + step * i, &input);
+                    input.pressed.clear();
+                }
             }
-            if new_y >= 0 && self.grid[new_y as usize][new_x as usize].is_some() {
-                return;
+            return Ok(());
+        }
+        let confirm = ctx.keyboard.is_key_just_pressed(KeyCode::Return) || std::mem::take(&mut self.pad_start);
+        // Letter keys type the name rather than acting as shortcuts while it is being entered
+        let typing = self.name_entry.is_some();
+
+        // Report controllers coming and going, pausing a game whose controller was unplugged
+        let gamepads = gamepad::connected(ctx);
+        if gamepads != self.gamepads {
+            let message = if gamepads > self.gamepads { "Controller connected" } else { "Controller disconnected" };
+            self.notice = Some((message.to_string(), now + NOTICE_DURATION));
+            let playing = self.screen == Screen::Playing && self.game.as_ref().is_some_and(|game| !game.paused);
+            if gamepads < self.gamepads && playing {
+                self.pressed.insert(Action::Pause);
             }
-            new_blocks.push(Block {
-                x: new_x,
-                y: new_y,
-                color: block.color,
-            });
+            self.gamepads = gamepads;
         }
 
-        self.tetromino.blocks = new_blocks;
-    }
+        // Mute works on every screen and is remembered across runs
+        if ctx.keyboard.is_key_just_pressed(KeyCode::M) && !typing {
+            self.music.toggle_mute();
+            self.settings.muted = self.music.is_muted();
+            if let Err(e) = self.settings.save_settings(config::CONFIG_FILE) {
+                eprintln!("Failed to save settings: {}", e);
+            }
+            let message = if self.music.is_muted() { "Music muted" } else { "Music on" };
+            self.notice = Some((message.to_string(), now + NOTICE_DURATION));
+        }
 
-    // Move the current tetromino by the specified amount
-    fn move_tetromino(&mut self, dx: i32, dy: i32) {
-        let mut can_move = true;
-        // Check if the move is valid
-        for block in &self.tetromino.blocks {
-            let new_x = block.x + dx;
-            let new_y = block.y + dy;
-            
-            if new_x < 0 || new_x >= GRID_WIDTH || new_y >= GRID_HEIGHT {
-                can_move = false;
-                break;
+        // Letter labels help tell pieces apart without relying on color
+        if ctx.keyboard.is_key_just_pressed(KeyCode::F2) && !typing {
+            self.settings.labels = !self.settings.labels;
+            if let Err(e) = self.settings.save_settings(config::CONFIG_FILE) {
+                eprintln!("Failed to save settings: {}", e);
             }
-            
-            if new_y >= 0 && self.grid[new_y as usize][new_x as usize].is_some() {
-                can_move = false;
-                break;
+            let message = if self.settings.labels { "Piece labels on" } else { "Piece labels off" };
+            self.notice = Some((message.to_string(), now + NOTICE_DURATION));
+        }
+
+        // Fullscreen switches without disturbing the game; the resize that follows refits the
+        // layout, and the choice is remembered across runs
+        if ctx.keyboard.is_key_just_pressed(KeyCode::F11) {
+            let fullscreen = !self.settings.fullscreen;
+            let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Windowed };
+            match ctx.gfx.set_fullscreen(mode) {
+                Ok(()) => {
+                    self.settings.fullscreen = fullscreen;
+                    if let Err(e) = self.settings.save_settings(config::CONFIG_FILE) {
+                        eprintln!("Failed to save settings: {}", e);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to change fullscreen mode: {}", e);
+                    self.notice = Some(("Fullscreen is not available".to_string(), now + NOTICE_DURATION));
+                }
             }
         }
 
-        if can_move {
-            // Perform the move
-            for block in &mut self.tetromino.blocks {
-                block.x += dx;
-                block.y += dy;
+        // The debug overlay works on every screen, like mute
+        if ctx.keyboard.is_key_just_pressed(KeyCode::F3) {
+            self.debug_open = !self.debug_open;
+        }
+
+        // Slow motion and fast forward for watching timing and animations, only with --debug
+        if let (true, Some(game)) = (self.debug_keys, &mut self.game) {
+            let scale = [(KeyCode::F6, 0.25), (KeyCode::F7, 1.0), (KeyCode::F8, 4.0)]
+                .into_iter()
+                .find(|&(key, _)| ctx.keyboard.is_key_just_pressed(key))
+                .map(|(_, scale)| scale);
+            if let Some(scale) = scale {
+                game.set_time_scale(scale);
+                self.notice = Some((format!("Game speed {}x", scale), now + NOTICE_DURATION));
             }
-        } else if dy > 0 {
-            // If we can't move down, freeze the tetromino
-            self.freeze_tetromino();
         }
-    }
 
-    // Freeze the current tetromino in place and create a new one
-    fn freeze_tetromino(&mut self) {
-        for block in &self.tetromino.blocks {
-            if block.y >= 0 {
-                self.grid[block.y as usize][block.x as usize] = Some(block.color);
-            } else {
-                self.game_over = true;
-                return;
+        // Help toggles over the menu, or pauses a game while it is showing
+        if ctx.keyboard.is_key_just_pressed(KeyCode::H) && !self.settings_open && !typing {
+            match (self.screen, &self.game) {
+                (Screen::Menu, _) => self.help_open = !self.help_open,
+                (Screen::Playing, Some(game)) => {
+                    if self.help_open == game.paused {
+                        self.pressed.insert(Action::Pause);
+                    }
+                    self.help_open = !self.help_open;
+                }
+                _ => {}
             }
         }
-        self.clear_lines();
-        self.tetromino = Self::create_random_tetromino();
-    }
 
-    // Check for and clear completed lines
-    fn clear_lines(&mut self) {
-        let mut lines_cleared = 0;
-        let mut y = GRID_HEIGHT - 1;
-        while y >= 0 {
-            if self.grid[y as usize].iter().all(|cell| cell.is_some()) {
-                lines_cleared += 1;
-                // Move all lines above down
-                for row in (1..=y).rev() {
-                    self.grid[row as usize] = self.grid[(row - 1) as usize].clone();
+        match self.screen {
+            Screen::Menu => {
+                if let Some(replay) = self.pending_replay.take() {
+                    let mut game = GameState::from_replay(&replay);
+                    game.start_clock(self.clock);
+                    self.game = Some(game);
+                    self.playback = Some(Playback { frames: replay.frames, next: 0, start: self.clock });
+                    self.screen = Screen::Playing;
+                } else if confirm || self.demo.is_some() {
+                    let mut config = self.config.clone();
+                    self.settings.difficulty.apply(&mut config);
+                    let mut game = GameState::new(config);
+                    if self.demo.is_none() {
+                        game.set_observer(Box::new(SessionObserver(Rc::clone(&self.session))));
+                    }
+                    game.start_clock(self.clock);
+                    self.game = Some(game);
+                    self.screen = Screen::Playing;
+                    self.help_open = false;
+                } else if ctx.keyboard.is_key_just_pressed(KeyCode::Left) || ctx.keyboard.is_key_just_pressed(KeyCode::Right) {
+                    let forward = ctx.keyboard.is_key_just_pressed(KeyCode::Right);
+                    self.settings.difficulty = self.settings.difficulty.cycle(forward);
+                    if let Err(e) = self.settings.save_settings(config::CONFIG_FILE) {
+                        eprintln!("Failed to save settings: {}", e);
+                    }
+                } else if ctx.keyboard.is_key_just_pressed(KeyCode::L) {
+                    match GameState::load_game(SAVE_FILE, self.clock) {
+                        Ok(mut game) => {
+                            game.set_observer(Box::new(SessionObserver(Rc::clone(&self.session))));
+                            self.game = Some(game);
+                            self.screen = Screen::Playing;
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to load game: {}", e);
+                            self.notice = Some(("No valid save game found".to_string(), now + NOTICE_DURATION));
+                        }
+                    }
                 }
-                self.grid[0] = vec![None; GRID_WIDTH as usize];
-            } else {
-                y -= 1;
             }
+            // The end screen waits for the name of a score that made the leaderboard
+            Screen::GameOver | Screen::Won if typing => {
+                if confirm {
+                    self.submit_name(now);
+                }
+            }
+            Screen::GameOver | Screen::Won if confirm => {
+                self.game = None;
+                self.pressed.clear();
+                self.screen = Screen::Menu;
+            }
+            Screen::Playing | Screen::GameOver | Screen::Won => {
+                if let Some(game) = &mut self.game {
+                    // The settings overlay opens from the pause screen and keeps the game
+                    // paused while it is showing
+                    if self.settings_open {
+                        let keyboard = &ctx.keyboard;
+                        if keyboard.is_key_just_pressed(KeyCode::Left) {
+                            self.settings_row = (self.settings_row + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+                        } else if keyboard.is_key_just_pressed(KeyCode::Right) {
+                            self.settings_row = (self.settings_row + 1) % SETTINGS_ROWS;
+                        }
+                        let step = if keyboard.is_key_just_pressed(KeyCode::Up) {
+                            1
+                        } else if keyboard.is_key_just_pressed(KeyCode::Down) {
+                            -1
+                        } else {
+                            0
+                        };
+                        if step != 0 {
+                            match self.settings_row {
+                                0 => {
+                                    let level = (game.start_level() + step).clamp(config::MIN_START_LEVEL, config::MAX_START_LEVEL);
+                                    game.set_start_level(level);
+                                    self.settings.start_level = level;
+                                    self.config.start_level = level;
+                                }
+                                1 => {
+                                    let interval = if step > 0 {
+                                        game.base_drop_interval() + DROP_SETTING_STEP
+                                    } else {
+                                        game.base_drop_interval().saturating_sub(DROP_SETTING_STEP)
+                                    }
+                                    .clamp(config::MIN_DROP_SETTING, config::MAX_DROP_SETTING);
+                                    game.set_base_drop_interval(interval);
+                                    self.settings.drop_interval = interval;
+                                    self.config.drop_interval = interval;
+                                }
+                                2 => {
+                                    let speed = if step > 0 {
+                                        game.soft_drop_speed() + SOFT_DROP_SETTING_STEP
+                                    } else {
+                                        game.soft_drop_speed().saturating_sub(SOFT_DROP_SETTING_STEP)
+                                    }
+                                    .clamp(config::MIN_SOFT_DROP_SPEED, config::MAX_SOFT_DROP_SPEED);
+                                    game.set_soft_drop_speed(speed);
+                                    self.settings.soft_drop_speed = speed;
+                                    self.config.soft_drop_speed = speed;
+                                }
+                                3 => {
+                                    let lock = match game.soft_drop_lock() {
+                                        SoftDropLock::Delayed => SoftDropLock::Instant,
+                                        SoftDropLock::Instant => SoftDropLock::Delayed,
+                                    };
+                                    game.set_soft_drop_lock(lock);
+                                    self.settings.soft_drop_lock = lock;
+                                    self.config.soft_drop_lock = lock;
+                                }
+                                4 => self.settings.shake = !self.settings.shake,
+                                5 => self.settings.patterns = !self.settings.patterns,
+                                6 => {
+                                    let limit = game.lock_reset_limit().saturating_add_signed(step).min(config::MAX_LOCK_RESETS);
+                                    game.set_lock_reset_limit(limit);
+                                    self.settings.lock_resets = limit;
+                                    self.config.lock_reset_limit = limit;
+                                }
+                                7 => self.settings.cell_highlight = !self.settings.cell_highlight,
+                                8 => {
+                                    let volume = if step > 0 {
+                                        self.music.volume() + VOLUME_SETTING_STEP
+                                    } else {
+                                        self.music.volume().saturating_sub(VOLUME_SETTING_STEP)
+                                    }
+                                    .min(config::MAX_MUSIC_VOLUME);
+                                    self.music.set_volume(volume);
+                                    self.settings.music_volume = volume;
+                                }
+                                _ => {}
+                            }
+                        }
+                        if keyboard.is_key_just_pressed(KeyCode::Tab) {
+                            self.settings_open = false;
+                            if let Err(e) = self.settings.save_settings(config::CONFIG_FILE) {
+                                eprintln!("Failed to save settings: {}", e);
+                                self.notice = Some(("Settings could not be saved".to_string(), now + NOTICE_DURATION));
+                            }
+                        }
+                        return Ok(());
+                    }
+                    if game.paused && ctx.keyboard.is_key_just_pressed(KeyCode::Tab) {
+                        self.settings_open = true;
+                        self.help_open = false;
+                        return Ok(());
+                    }
+
+                    if ctx.keyboard.is_key_just_pressed(KeyCode::F5) && !game.game_over {
+                        let message = match game.save_game(SAVE_FILE) {
+                            Ok(()) => "Game saved".to_string(),
+                            Err(e) => {
+                                eprintln!("Failed to save game: {}", e);
+                                "Save failed".to_string()
+                            }
+                        };
+                        self.notice = Some((message, now + NOTICE_DURATION));
+                    }
+                    let replaying = self.playback.is_some();
+                    if let Some(playback) = &mut self.playback {
+                        // Apply every recorded update that is due, at the time it originally ran
+                        while let Some(frame) = playback.frames.get(playback.next) {
+                            let time = playback.start + frame.time;
+                            if time > self.clock {
+                                break;
+                            }
+                            game.update(time, &frame.input);
+                            playback.next += 1;
+                        }
+                        if playback.next == playback.frames.len() {
+                            self.playback = None;
+                        }
+                        self.pressed.clear();
+                    } else if let (Some(demo), true) = (&mut self.demo, steps > 0) {
+                        // The demo player decides every step, though the player may still pause
+                        let pause = std::mem::take(&mut self.pressed).contains(&Action::Pause);
+                        for i in 1..=steps {
+                            let mut input = demo.input(game);
+                            if pause && i == 1 {
+                                input.pressed.insert(Action::Pause);
+                            }
+                            game.update(first_step + step * i, &input);
+                        }
+                    } else if steps > 0 {
+                        let mut input = read_input(ctx, &self.settings.keys);
+                        // The keyboard takes over steering until the mouse moves again
+                        if input.held.contains(&Action::MoveLeft) || input.held.contains(&Action::MoveRight) {
+                            self.mouse_column = None;
+                        }
+                        input.target_column = self.mouse_column;
+                        // Presses since the last frame go to the first step only
+                        input.pressed = std::mem::take(&mut self.pressed);
+                        input.forced_piece = self.forced_piece.take();
+                        for i in 1..=steps {
+                            game.update(first_step + step * i, &input);
+                            input.pressed.clear();
+                            input.forced_piece = None;
+                        }
+                    }
+                    for event in game.drain_events() {
+                        match event {
+                            GameEvent::TSpin(_) => {
+                                self.banner = Some(("T-Spin!".to_string(), now + BANNER_DURATION));
+                            }
+                            GameEvent::PerfectClear => {
+                                self.banner = Some(("All Clear!".to_string(), now + BANNER_DURATION));
+                            }
+                            GameEvent::StackReset => {
+                                self.banner = Some(("Stack cleared".to_string(), now + BANNER_DURATION));
+                            }
+                            GameEvent::LinesCleared(_) => {
+                                if let Some(clear) = &game.last_clear {
+                                    self.breakdown = Some((describe_clear(clear), now + BANNER_DURATION));
+                                }
+                            }
+                            _ => {}
+                        }
+                        self.sounds.play(ctx, event);
+                    }
+                    // Help goes away once the game resumes or ends
+                    if !game.paused && !self.pressed.contains(&Action::Pause) {
+                        self.help_open = false;
+                    }
+                    // A live game that ends with a qualifying score asks for the player's name;
+                    // practice and puzzle games, with their hand-picked pieces or no
+                    // gravity, don't count
+                    let ended = self.screen == Screen::Playing && game.game_over;
+                    let counts = !replaying && self.demo.is_none() && !game.practice && !game.puzzle;
+                    if ended && counts && self.leaderboard.qualifies(game.score) {
+                        self.name_entry = Some(String::new());
+                    }
+                    // Restarting from game over puts the game back in play
+                    self.screen = if game.won {
+                        Screen::Won
+                    } else if game.game_over {
+                        Screen::GameOver
+                    } else {
+                        Screen::Playing
+                    };
+                }
+            }
+        }
+
+        // Report each new game's seed so a good run can be replayed with --seed
+        if let Some(game) = &self.game {
+            if self.announced_seed != Some(game.seed()) {
+                println!("Piece seed: {}", game.seed());
+                self.announced_seed = Some(game.seed());
+            }
+        }
+
+        // A replay, save or return to the menu can change the grid size, so refit the layout
+        // to the window
+        if self.grid_size() != self.fitted_grid {
+            let (width, height) = ctx.gfx.drawable_size();
+            event::EventHandler::resize_event(self, ctx, width, height)?;
         }
 
-        // Calculate score based on number of lines cleared
-        match lines_cleared {
-            1 => self.score += 100,
-            2 => self.score += 300,
-            3 => self.score += 500,
-            4 => self.score += 800,
-            _ => (),
+        // Music plays only while a game is in progress
+        if self.screen == Screen::Playing {
+            self.music.start(ctx);
+        } else {
+            self.music.stop(ctx);
         }
+        Ok(())
     }
-}
 
-// Implement the game loop handlers
-impl event::EventHandler<ggez::GameError> for GameState {
-    fn update(&mut self, ctx: &mut Context) -> GameResult {
-        if self.game_over {
+    // Queue one-shot actions on key down, ignoring auto-repeat so a held key acts only once
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, repeated: bool) -> GameResult {
+        // The quit prompt takes every key until it is answered; saving first only quits if
+        // the save worked
+        if let Some(paused_it) = self.quit_prompt {
+            match input.keycode {
+                Some(KeyCode::Y) => {
+                    self.quit_confirmed = true;
+                    ctx.request_quit();
+                }
+                Some(KeyCode::S) => {
+                    if let Some(game) = &self.game {
+                        match game.save_game(SAVE_FILE) {
+                            Ok(()) => {
+                                self.quit_confirmed = true;
+                                ctx.request_quit();
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to save game: {}", e);
+                                self.notice = Some(("Save failed".to_string(), ctx.time.time_since_start() + NOTICE_DURATION));
+                            }
+                        }
+                    }
+                }
+                Some(KeyCode::N | KeyCode::Escape) => {
+                    self.quit_prompt = None;
+                    if paused_it {
+                        self.pressed.insert(Action::Pause);
+                    }
+                }
+                _ => {}
+            }
             return Ok(());
         }
+        // Escape quits like closing the window, asking first during a game
+        if input.keycode == Some(KeyCode::Escape) {
+            ctx.request_quit();
+        }
+        if let Some(name) = &mut self.name_entry {
+            if input.keycode == Some(KeyCode::Back) {
+                name.pop();
+            }
+            return Ok(());
+        }
+        let in_game = matches!(self.screen, Screen::Playing | Screen::GameOver | Screen::Won);
+        if repeated || !in_game || self.settings_open {
+            return Ok(());
+        }
+        if let Some(action) = input.keycode.and_then(|key| self.settings.keys.action_for(key)) {
+            if !action.is_continuous() {
+                self.pressed.insert(action);
+            }
+        } else if let Some(block_type) = input.keycode.and_then(piece_key) {
+            // In practice mode the number keys pick the next piece, in piece set order
+            if self.game.as_ref().is_some_and(|game| game.practice) {
+                self.forced_piece = Some(block_type);
+            }
+        }
+        Ok(())
+    }
 
-        let now = timer::time_since_start(ctx);
-
-        // Handle left/right movement
-        if now - self.last_move_time >= MOVE_INTERVAL {
-            if ctx.keyboard.is_key_pressed(KeyCode::Left) {
-                self.move_tetromino(-1, 0);
-                self.last_move_time = now;
+    // Queue one-shot actions from controller buttons; Start pauses a game and confirms on
+    // the menu and end screens
+    fn gamepad_button_down_event(&mut self, _ctx: &mut Context, button: Button, _id: GamepadId) -> GameResult {
+        if self.settings_open || self.quit_prompt.is_some() {
+            return Ok(());
+        }
+        match (self.screen, button) {
+            (Screen::Playing, Button::Start) => {
+                self.pressed.insert(Action::Pause);
             }
-            if ctx.keyboard.is_key_pressed(KeyCode::Right) {
-                self.move_tetromino(1, 0);
-                self.last_move_time = now;
+            (_, Button::Start) => self.pad_start = true,
+            (Screen::Menu, _) => {}
+            _ => {
+                if let Some(action) = gamepad::button_action(button) {
+                    self.pressed.insert(action);
+                }
             }
         }
+        Ok(())
+    }
 
-        // Handle fast drop
-        if ctx.keyboard.is_key_pressed(KeyCode::Down) {
-            if now - self.last_move_time >= MOVE_INTERVAL {
-                self.move_tetromino(0, 1);
-                self.last_move_time = now;
+    // Type the name for a new leaderboard score
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> GameResult {
+        if let Some(name) = &mut self.name_entry {
+            if (character.is_alphanumeric() || character == ' ') && name.chars().count() < leaderboard::MAX_NAME_LEN {
+                name.push(character);
             }
         }
+        Ok(())
+    }
 
-        // Handle rotation
-        if ctx.keyboard.is_key_pressed(KeyCode::Up) {
-            if now - self.last_rotate_time >= MOVE_INTERVAL {
-                self.rotate_tetromino();
-                self.last_rotate_time = now;
+    // Steer the falling piece toward the column under the mouse
+    fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, _y: f32, _dx: f32, _dy: f32) -> GameResult {
+        if let Some(game) = &self.game {
+            let column = ((x - self.offset.0) / self.block_size).floor() as i32;
+            self.mouse_column = (0..game.width).contains(&column).then_some(column);
+        }
+        Ok(())
+    }
+
+    // Left click hard drops and right click rotates
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) -> GameResult {
+        if self.screen == Screen::Playing && self.quit_prompt.is_none() {
+            match button {
+                MouseButton::Left => {
+                    self.pressed.insert(Action::HardDrop);
+                }
+                MouseButton::Right => {
+                    self.pressed.insert(Action::RotateCw);
+                }
+                _ => {}
             }
         }
+        Ok(())
+    }
 
-        // Handle automatic dropping
-        if now - self.last_drop_time >= DROP_INTERVAL {
-            self.move_tetromino(0, 1);
-            self.last_drop_time = now;
+    // Ask before closing the window on a live game, pausing it meanwhile; the menu, end
+    // screens, demo and replays close straight away
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        let live = self.screen == Screen::Playing && self.demo.is_none() && self.playback.is_none();
+        if self.quit_confirmed || !live {
+            return Ok(false);
+        }
+        if self.quit_prompt.is_none() {
+            let paused = self.game.as_ref().is_some_and(|game| game.paused);
+            if !paused {
+                self.pressed.insert(Action::Pause);
+            }
+            self.quit_prompt = Some(!paused);
         }
+        Ok(true)
+    }
 
+    // Refit the block size to the new window, keeping the board's proportions and centering
+    // it with empty bars on the sides that don't fill
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        self.fitted_grid = self.grid_size();
+        let (grid_width, rows) = self.fitted_grid;
+        let columns = layout_columns(grid_width, self.preview_width) as f32;
+        let rows = rows as f32;
+        self.block_size = (width / columns).min(height / rows).floor().max(MIN_BLOCK_SIZE);
+        self.block_mesh = build_block_mesh(ctx, self.block_size)?;
+        self.pattern_meshes = build_pattern_meshes(ctx, self.block_size)?;
+        let (layout_width, layout_height) = self.layout_size();
+        self.offset = (((width - layout_width) / 2.0).max(0.0), ((height - layout_height) / 2.0).max(0.0));
         Ok(())
     }
 
-    // Draw the game state
+    // Draw the current screen
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         let mut canvas = graphics::Canvas::from_frame(ctx, Color::BLACK);
+        // Shift the origin so the layout sits centered in the window
+        let (window_width, window_height) = ctx.gfx.drawable_size();
+        let (offset_x, offset_y) = self.offset;
+        canvas.set_screen_coordinates(graphics::Rect::new(-offset_x, -offset_y, window_width, window_height));
+
+        match &self.game {
+            Some(game) if self.screen != Screen::Menu => self.draw_game(ctx, &mut canvas, game)?,
+            _ => {
+                let (width, height) = self.layout_size();
+                draw_menu(&mut canvas, (width, height), self.unit(), self.settings.difficulty);
+                let session = self.session.borrow();
+                if session.games > 0 {
+                    let mut totals = scaled_text(self.unit(), format!("This session: {} games, {} lines", session.games, session.lines));
+                    totals.set_layout(graphics::TextLayout { h_align: graphics::TextAlign::Middle, v_align: graphics::TextAlign::Begin });
+                    canvas.draw(&totals, DrawParam::default().dest([width / 2.0, 20.0 * self.unit()]).color(Color::new(0.6, 0.6, 0.6, 1.0)));
+                }
+                self.draw_leaderboard(&mut canvas, width / 2.0, height / 2.0 + 65.0 * self.unit());
+            }
+        }
+        if self.help_open {
+            self.draw_help(ctx, &mut canvas)?;
+        }
+        if self.debug_open {
+            self.draw_debug(ctx, &mut canvas)?;
+        }
+
+        // Draw the current status message along the bottom of the window
+        if let Some((message, until)) = &self.notice {
+            if ctx.time.time_since_start() < *until {
+                let (_, height) = self.layout_size();
+                let unit = self.unit();
+                canvas.draw(
+                    &scaled_text(unit, message.as_str()),
+                    DrawParam::default()
+                        .dest([10.0 * unit, height - 25.0 * unit])
+                        .color(Color::YELLOW),
+                );
+            }
+        }
+
+        canvas.finish(ctx)?;
+        Ok(())
+    }
+}
+
+impl MainState {
+    // Draw the playfield, side panel and overlays for a game in progress or just ended
+    fn draw_game(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, game: &GameState) -> GameResult {
+        let (block_size, unit) = (self.block_size, self.unit());
+        let field_width = game.width as f32 * block_size;
+        let visible_rows = game.height - game.hidden_rows;
+        let field_height = visible_rows as f32 * block_size;
+        let grid_height = game.height as f32 * block_size;
+        let hidden_height = game.hidden_rows as f32 * block_size;
+
+        // Draw faint grid lines behind everything else, batched into a single mesh
+        let grid_color = Color::new(0.15, 0.15, 0.15, 1.0);
+        let mut grid_lines = graphics::MeshBuilder::new();
+        for x in 1..game.width {
+            let px = x as f32 * block_size;
+            grid_lines.line(&[[px, 0.0], [px, field_height]], 1.0, grid_color)?;
+        }
+        for y in 1..visible_rows {
+            let py = y as f32 * block_size;
+            grid_lines.line(&[[0.0, py], [field_width, py]], 1.0, grid_color)?;
+        }
+        let grid_mesh = graphics::Mesh::from_data(ctx, grid_lines.build());
+        canvas.draw(&grid_mesh, DrawParam::default());
 
-        // Draw the current tetromino
-        for block in &self.tetromino.blocks {
-            let rect = graphics::Mesh::new_rectangle(
+        // Outline the playfield in the theme's border color so its edges stay visible
+        let border = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::stroke(2.0 * unit),
+            graphics::Rect::new(0.0, 0.0, field_width, field_height),
+            game.theme().border().into(),
+        )?;
+        canvas.draw(&border, DrawParam::default());
+
+        // Draw the grid's contents in grid coordinates shifted up past the hidden rows, and by
+        // any line clear shake, clipped to the visible field so pieces spawning in the hidden
+        // rows show only their lower part and shaken cells never spill out of the board
+        let (shake_x, shake_y) = if self.settings.shake { game.shake_offset() } else { (0.0, 0.0) };
+        let screen = canvas.screen_coordinates();
+        if let Some(screen) = screen {
+            let (x, y) = (screen.x - shake_x * block_size, screen.y + hidden_height - shake_y * block_size);
+            canvas.set_screen_coordinates(graphics::Rect::new(x, y, screen.w, screen.h));
+        }
+        let (offset_x, offset_y) = self.offset;
+        canvas.set_scissor_rect(graphics::Rect::new(offset_x, offset_y, field_width, field_height))?;
+
+        // Pulse a red tint over the top visible rows while the stack reaches into them, as a
+        // warning before the next spawn can top out
+        let highest_row = game.grid.iter().position(|row| row.iter().any(|cell| cell.is_some()));
+        if highest_row.is_some_and(|row| row < game.hidden_rows as usize + DANGER_ROWS) {
+            let pulse = (ctx.time.time_since_start().as_secs_f32() * std::f32::consts::TAU).sin() * 0.5 + 0.5;
+            let danger = graphics::Mesh::new_rectangle(
                 ctx,
                 graphics::DrawMode::fill(),
-                graphics::Rect::new(
-                    block.x as f32 * BLOCK_SIZE,
-                    block.y as f32 * BLOCK_SIZE,
-                    BLOCK_SIZE - 1.0,
-                    BLOCK_SIZE - 1.0,
-                ),
-                block.color,
+                graphics::Rect::new(0.0, hidden_height, field_width, DANGER_ROWS as f32 * block_size),
+                Color::new(1.0, 0.0, 0.0, 0.1 + 0.15 * pulse),
             )?;
-            canvas.draw(&rect, DrawParam::default());
+            canvas.draw(&danger, DrawParam::default());
         }
 
-        // Draw the frozen blocks
-        for (y, row) in self.grid.iter().enumerate() {
-            for (x, cell) in row.iter().enumerate() {
-                if let Some(color) = cell {
-                    let rect = graphics::Mesh::new_rectangle(
-                        ctx,
+        // Tint the columns under the current tetromino down to the floor as a faint drop guide;
+        // frozen blocks are drawn on top so the tint never hides them
+        let falling = !game.game_over && !game.is_clearing() && !game.is_lock_flashing();
+        let landing = if falling { game.landing_offset() } else { 0 };
+        if falling {
+            let mut columns = graphics::MeshBuilder::new();
+            let mut any_column = false;
+            let mut xs: Vec<i32> = game.tetromino.cells().map(|(x, _)| x).collect();
+            xs.sort_unstable();
+            xs.dedup();
+            for x in xs {
+                let bottom = game.tetromino.cells().filter(|&(cx, _)| cx == x).map(|(_, y)| y).max().unwrap_or(0);
+                let top = (bottom + 1).max(0) as f32 * block_size;
+                if top < grid_height {
+                    columns.rectangle(
                         graphics::DrawMode::fill(),
-                        graphics::Rect::new(
-                            x as f32 * BLOCK_SIZE,
-                            y as f32 * BLOCK_SIZE,
-                            BLOCK_SIZE - 1.0,
-                            BLOCK_SIZE - 1.0,
-                        ),
-                        *color,
+                        graphics::Rect::new(x as f32 * block_size, top, block_size, grid_height - top),
+                        Color::new(1.0, 1.0, 1.0, 0.06),
                     )?;
-                    canvas.draw(&rect, DrawParam::default());
+                    any_column = true;
                 }
             }
+            // An empty mesh can't be uploaded, so skip the draw when the piece sits on the floor
+            if any_column {
+                let columns_mesh = graphics::Mesh::from_data(ctx, columns.build());
+                canvas.draw(&columns_mesh, DrawParam::default());
+            }
         }
 
-        // Draw the score
-        let score_text = Text::new(format!("Score: {}", self.score));
-        canvas.draw(
-            &score_text,
-            DrawParam::default()
-                .dest([10.0, 10.0])
-                .color(Color::WHITE),
-        );
+        // Draw the ghost piece where the current tetromino would land
+        if falling {
+            let ghost = game.tetromino.translated(0, landing);
+            for block in &ghost.blocks {
+                // Skip cells already covered by the falling piece itself
+                if game.tetromino.cells().any(|cell| cell == (block.x, block.y)) {
+                    continue;
+                }
+                draw_block(
+                    canvas,
+                    &self.block_mesh,
+                    block.x as f32 * block_size,
+                    block.y as f32 * block_size,
+                    Color::new(block.color.r, block.color.g, block.color.b, 0.3),
+                );
+            }
+        }
+
+        // Draw the hard drop trail as narrow streaks that fade out
+        if !game.particles.is_empty() {
+            let mut trail = graphics::MeshBuilder::new();
+            for particle in &game.particles {
+                let color = particle.color;
+                trail.rectangle(
+                    graphics::DrawMode::fill(),
+                    graphics::Rect::new(
+                        particle.x as f32 * block_size + block_size / 3.0,
+                        particle.y as f32 * block_size,
+                        block_size / 3.0,
+                        block_size,
+                    ),
+                    Color::new(color.r, color.g, color.b, 0.5 * particle.life),
+                )?;
+            }
+            let trail_mesh = graphics::Mesh::from_data(ctx, trail.build());
+            canvas.draw(&trail_mesh, DrawParam::default());
+        }
+
+        // Draw the current tetromino, brightened while it flashes on locking; while rows flash
+        // it is already part of the grid
+        if !game.is_clearing() {
+            let flashing = game.is_lock_flashing();
+            let name = game.piece_name(game.tetromino.block_type);
+            for block in &game.tetromino.blocks {
+                let color = if flashing { brighten(block.color.into()) } else { block.color.into() };
+                draw_block(canvas, &self.block_mesh, block.x as f32 * block_size, block.y as f32 * block_size, color);
+                self.draw_pattern(canvas, game.tetromino.block_type, block.x as f32, block.y as f32);
+                self.draw_label(canvas, name, block.x as f32, block.y as f32);
+            }
+        }
+
+        // Draw the frozen blocks, with rows being cleared flashing white and the rows above a
+        // clear sliding down into place
+        for (y, row) in game.grid.iter().enumerate() {
+            let flashing = game.clearing_rows.contains(&(y as i32));
+            let draw_y = (y as f32 - game.collapse_offset(y)) * block_size;
+            for (x, cell) in row.iter().enumerate() {
+                if let Some(cell) = cell {
+                    let color = if flashing { Color::WHITE } else { cell.color.into() };
+                    draw_block(canvas, &self.block_mesh, x as f32 * block_size, draw_y, color);
+                    if let Some(block_type) = cell.block_type {
+                        self.draw_pattern(canvas, block_type, x as f32, y as f32 - game.collapse_offset(y));
+                        self.draw_label(canvas, game.piece_name(block_type), x as f32, y as f32 - game.collapse_offset(y));
+                    }
+                }
+            }
+        }
 
-        // Draw game over message if applicable
-        if self.game_over {
-            let game_over_text = Text::new("Game Over!");
+        // Label the falling piece with how many rows it can still drop, beside its lowest
+        // block on whichever side has room, on top of any frozen blocks there
+        if falling && landing > 0 {
+            let lowest = game.tetromino.blocks.iter().max_by_key(|block| (block.y, block.x)).unwrap();
+            let (left, _, right, _) = game.tetromino.bounding_box();
+            let x = if right + 1 < game.width {
+                (right + 1) as f32 * block_size + 4.0 * unit
+            } else {
+                left as f32 * block_size - 12.0 * unit
+            };
             canvas.draw(
-                &game_over_text,
+                &scaled_text(unit, landing.to_string()),
                 DrawParam::default()
-                    .dest([SCREEN_WIDTH / 2.0 - 40.0, SCREEN_HEIGHT / 2.0])
-                    .color(Color::RED),
+                    .dest([x, lowest.y as f32 * block_size + 4.0 * unit])
+                    .color(Color::new(1.0, 1.0, 1.0, 0.7)),
             );
         }
 
-        canvas.finish(ctx)?;
+        // Float the points from recent line clears up over the field as they fade
+        for popup in &game.popups {
+            let mut text = scaled_text(unit, popup.text.as_str());
+            text.set_layout(graphics::TextLayout::center());
+            canvas.draw(
+                &text,
+                DrawParam::default()
+                    .dest([popup.x * block_size, popup.y * block_size])
+                    .color(Color::new(1.0, 1.0, 0.4, popup.life)),
+            );
+        }
+
+        // Back to the layout's own coordinates for everything outside the grid
+        if let Some(screen) = screen {
+            canvas.set_screen_coordinates(screen);
+        }
+        canvas.set_default_scissor_rect();
+
+        // Outline the visible cell under the mouse as a building aid, mapping the window
+        // position back through the centering offset
+        if self.settings.cell_highlight {
+            let mouse = ctx.mouse.position();
+            let x = ((mouse.x - offset_x) / block_size).floor();
+            let y = ((mouse.y - offset_y) / block_size).floor();
+            if (0.0..game.width as f32).contains(&x) && (0.0..visible_rows as f32).contains(&y) {
+                let cell = graphics::Rect::new(x * block_size, y * block_size, block_size, block_size);
+                let outline = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(1.0), cell, Color::new(1.0, 1.0, 1.0, 0.6))?;
+                canvas.draw(&outline, DrawParam::default());
+            }
+        }
+
+        let hud = HudLayout::new(field_width, field_height, self.preview_width, block_size, unit);
+
+        // Draw the next piece preview to the right of the playfield
+        canvas.draw(&scaled_text(unit, "Next:"), DrawParam::default().dest(hud.next_label()).color(Color::WHITE));
+        for (i, block_type) in game.upcoming().into_iter().enumerate() {
+            draw_mini_piece(canvas, &self.block_mesh, block_size, game.piece_shape(block_type), hud.next_slot(i), block_size);
+        }
+
+        // Draw the held piece in a labeled box below the queue, dimmed while hold is used up
+        // for the current piece
+        let hold_color = if game.hold_used { Color::new(0.4, 0.4, 0.4, 1.0) } else { Color::WHITE };
+        canvas.draw(&scaled_text(unit, "Hold:"), DrawParam::default().dest(hud.hold_label()).color(hold_color));
+        let hold_rect = hud.hold_box();
+        let hold_box = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(1.0), hold_rect, hold_color)?;
+        canvas.draw(&hold_box, DrawParam::default());
+        if let Some(held_type) = game.held_tetromino {
+            let slot = graphics::Rect::new(hold_rect.x + 5.0 * unit, hold_rect.y + 5.0 * unit, block_size * 4.0, block_size * 2.0);
+            draw_mini_piece(canvas, &self.block_mesh, block_size, game.piece_shape(held_type), slot, block_size);
+            if game.hold_used {
+                let shade = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), hold_rect, Color::new(0.0, 0.0, 0.0, 0.6))?;
+                canvas.draw(&shade, DrawParam::default());
+            }
+        }
+
+        // Fill the score column line by line, so readouts that come and go push the ones
+        // below down instead of overlapping them
+        let level = match game.target_level() {
+            Some(target) => format!("{}/{}", game.level, target),
+            None => game.level.to_string(),
+        };
+        let lines = match game.target_lines() {
+            Some(target) => format!("{}/{}", game.total_lines, target),
+            None => game.total_lines.to_string(),
+        };
+        let elapsed = game.elapsed().as_secs();
+        for (line, text) in [format!("Score: {}", game.score), format!("Level: {}", level)].into_iter().enumerate() {
+            canvas.draw(&scaled_text(unit, text), DrawParam::default().dest(hud.stats_line(line)).color(Color::WHITE));
+        }
+        let mut line = 2;
+
+        // Fill a bar below the level with the lines cleared towards the next level
+        let (progress, lines_per_level) = game.level_progress();
+        let bar = hud.level_bar(line);
+        if progress > 0 {
+            let filled = graphics::Rect::new(bar.x, bar.y, bar.w * progress as f32 / lines_per_level as f32, bar.h);
+            let fill = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), filled, Color::GREEN)?;
+            canvas.draw(&fill, DrawParam::default());
+        }
+        let outline = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(1.0), bar, Color::WHITE)?;
+        canvas.draw(&outline, DrawParam::default());
+        line += 1;
+
+        for text in [
+            format!("Lines: {}", lines),
+            format!("High: {}", game.high_score),
+            String::new(),
+            format!("Time: {}:{:02}", elapsed / 60, elapsed % 60),
+            format!("PPM: {:.1}", game.pieces_per_minute()),
+            String::new(),
+        ] {
+            if !text.is_empty() {
+                canvas.draw(&scaled_text(unit, text), DrawParam::default().dest(hud.stats_line(line)).color(Color::WHITE));
+            }
+            line += 1;
+        }
+
+        // Then whatever is currently in effect: streaks, then who is playing and the mode
+        let combo = format!("Combo: {}", game.combo);
+        let indicators = [
+            (game.combo > 0, combo.as_str(), Color::YELLOW),
+            (game.last_clear_was_tetris, "B2B", Color::CYAN),
+            (self.playback.is_some(), "REPLAY", Color::RED),
+            (self.demo.is_some(), "DEMO", Color::RED),
+            (game.zen, "ZEN", Color::CYAN),
+            (game.practice, "PRACTICE", Color::GREEN),
+            (game.twenty_g, "20G", Color::YELLOW),
+            (game.puzzle, "PUZZLE", Color::MAGENTA),
+        ];
+        for (_, text, color) in indicators.into_iter().filter(|&(shown, _, _)| shown) {
+            canvas.draw(&scaled_text(unit, text), DrawParam::default().dest(hud.stats_line(line)).color(color));
+            line += 1;
+        }
+
+        // Keep a running count of each piece type dealt in its own column
+        self.draw_spawn_counts(canvas, game, &hud);
+
+        // Briefly itemize the points from the last line clear below the rest
+        if let Some((breakdown, until)) = &self.breakdown {
+            if ctx.time.time_since_start() < *until {
+                canvas.draw(
+                    &scaled_text(unit, breakdown.as_str()),
+                    DrawParam::default()
+                        .dest(hud.stats_line(line + 1))
+                        .color(Color::YELLOW),
+                );
+            }
+        }
+
+        // Draw the current gameplay banner across the middle of the playfield
+        if let Some((message, until)) = &self.banner {
+            if ctx.time.time_since_start() < *until {
+                canvas.draw(
+                    &scaled_text(unit, message.as_str()),
+                    DrawParam::default()
+                        .dest([field_width / 2.0 - 30.0 * unit, field_height / 3.0])
+                        .color(Color::MAGENTA),
+                );
+            }
+        }
+
+        // Count down the last seconds before play starts, large in the middle of the playfield
+        if let Some(seconds) = game.countdown() {
+            let mut countdown_text = Text::new(seconds.to_string());
+            countdown_text.set_scale(TEXT_SIZE * 4.0 * unit);
+            countdown_text.set_layout(graphics::TextLayout::center());
+            canvas.draw(
+                &countdown_text,
+                DrawParam::default()
+                    .dest([field_width / 2.0, field_height / 2.0])
+                    .color(Color::WHITE),
+            );
+            // Point out the one free swap of the opening piece while it is still available
+            if !game.opening_swapped {
+                let key = keys::key_name(self.settings.keys.hold);
+                let mut hint = scaled_text(unit, format!("{}: swap first piece", key));
+                hint.set_layout(graphics::TextLayout::center());
+                canvas.draw(
+                    &hint,
+                    DrawParam::default()
+                        .dest([field_width / 2.0, field_height / 2.0 + 50.0 * unit])
+                        .color(Color::new(1.0, 1.0, 1.0, 0.7)),
+                );
+            }
+        }
+
+        // Draw the pause overlay on top of the playfield
+        if game.paused {
+            let overlay = graphics::Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(0.0, 0.0, field_width, field_height),
+                Color::new(0.0, 0.0, 0.0, 0.6),
+            )?;
+            canvas.draw(&overlay, DrawParam::default());
+            if self.quit_prompt.is_some() {
+                let quit_text = scaled_text(unit, "Quit? Y/N\n\nS: save and quit");
+                canvas.draw(
+                    &quit_text,
+                    DrawParam::default()
+                        .dest([field_width / 2.0 - 60.0 * unit, field_height / 2.0])
+                        .color(Color::WHITE),
+                );
+            } else if self.settings_open {
+                self.draw_settings(canvas, game, field_width, field_height);
+            } else {
+                let paused_text = scaled_text(unit, "Paused\n\nTab: settings");
+                canvas.draw(
+                    &paused_text,
+                    DrawParam::default()
+                        .dest([field_width / 2.0 - 50.0 * unit, field_height / 2.0])
+                        .color(Color::WHITE),
+                );
+            }
+        }
+
+        // Draw the end-of-game message: the time taken for a finished sprint, the ending of a
+        // finished marathon, or game over
+        if game.game_over {
+            if game.won && game.target_level().is_some() {
+                let win_text = scaled_text(unit, format!(
+                    "Congratulations!\nLevel {} reached\nScore: {}",
+                    game.level, game.score
+                ));
+                canvas.draw(
+                    &win_text,
+                    DrawParam::default()
+                        .dest([field_width / 2.0 - 60.0 * unit, field_height / 2.0 - 40.0 * unit])
+                        .color(Color::GREEN),
+                );
+            } else if game.won {
+                let elapsed = game.elapsed();
+                let win_text = scaled_text(unit, format!(
+                    "You Win!\nTime: {}:{:02}.{:02}",
+                    elapsed.as_secs() / 60,
+                    elapsed.as_secs() % 60,
+                    elapsed.subsec_millis() / 10
+                ));
+                canvas.draw(
+                    &win_text,
+                    DrawParam::default()
+                        .dest([field_width / 2.0 - 40.0 * unit, field_height / 2.0 - 20.0 * unit])
+                        .color(Color::GREEN),
+                );
+            } else {
+                let game_over_text = scaled_text(unit, "Game Over!");
+                canvas.draw(
+                    &game_over_text,
+                    DrawParam::default()
+                        .dest([field_width / 2.0 - 40.0 * unit, field_height / 2.0])
+                        .color(Color::RED),
+                );
+            }
+            let prompt = match &self.name_entry {
+                Some(name) => format!("New high score!\nName: {}_\nPress Enter to save", name),
+                None => "Press R to restart\nPress Enter for menu".to_string(),
+            };
+            canvas.draw(
+                &scaled_text(unit, prompt),
+                DrawParam::default()
+                    .dest([field_width / 2.0 - 70.0 * unit, field_height / 2.0 + 25.0 * unit])
+                    .color(Color::WHITE),
+            );
+            self.draw_leaderboard(canvas, field_width / 2.0, field_height / 2.0 + 90.0 * unit);
+        }
+        Ok(())
+    }
+
+    // Draw a miniature of each piece type with the number dealt so far, down the side panel's
+    // piece count column
+    fn draw_spawn_counts(&self, canvas: &mut graphics::Canvas, game: &GameState, hud: &HudLayout) {
+        let unit = self.unit();
+        canvas.draw(&scaled_text(unit, "Pieces:"), DrawParam::default().dest(hud.counts_label()).color(Color::WHITE));
+        let types = game.spawn_counts.len();
+        for (i, &count) in game.spawn_counts.iter().enumerate() {
+            let slot = hud.count_slot(i, types);
+            draw_mini_piece(canvas, &self.block_mesh, self.block_size, game.piece_shape(i as i32), slot, 6.0 * unit);
+            canvas.draw(
+                &scaled_text(unit, count.to_string()),
+                DrawParam::default()
+                    .dest([slot.x + slot.w + 6.0 * unit, slot.y])
+                    .color(Color::WHITE),
+            );
+        }
+    }
+
+    // Draw the frame rate and, during a game, the falling piece, running timers and seed in
+    // the top-left corner over a dark backing
+    fn draw_debug(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let mut lines = vec![format!("FPS: {:.1}", ctx.time.fps())];
+        if let Some(game) = &self.game {
+            let piece = &game.tetromino;
+            let cells: Vec<String> = piece.blocks.iter().map(|block| format!("{},{}", block.x, block.y)).collect();
+            lines.push(format!("Piece: {} r{}", game.piece_name(piece.block_type), piece.rotation()));
+            lines.push(format!("  {}", cells.join(" ")));
+            for (name, time) in game.timers() {
+                lines.push(format!("{}: {} ms", name, time.as_millis()));
+            }
+            lines.push(format!("Stack: height {}, holes {}", game.aggregate_height(), game.hole_count()));
+            lines.push(format!("Seed: {}", game.seed()));
+            if game.time_scale() != 1.0 {
+                lines.push(format!("Speed: {}x", game.time_scale()));
+            }
+        }
+        let unit = self.unit();
+        let mut text = Text::new(lines.join("\n"));
+        text.set_scale(TEXT_SIZE * 0.75 * unit);
+        let size = text.measure(ctx)?;
+        let backing = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, size.x + 8.0 * unit, size.y + 8.0 * unit),
+            Color::new(0.0, 0.0, 0.0, 0.75),
+        )?;
+        canvas.draw(&backing, DrawParam::default());
+        canvas.draw(&text, DrawParam::default().dest([4.0 * unit, 4.0 * unit]).color(Color::GREEN));
+        Ok(())
+    }
+
+    // Draw the leaderboard centered on x below y, with the latest entry highlighted
+    fn draw_leaderboard(&self, canvas: &mut graphics::Canvas, x: f32, y: f32) {
+        if self.leaderboard.entries.is_empty() {
+            return;
+        }
+        let unit = self.unit();
+        let mut table = Text::new(TextFragment::new("High Scores\n").color(Color::CYAN));
+        for (rank, entry) in self.leaderboard.entries.iter().enumerate() {
+            let color = if self.new_rank == Some(rank) { Color::YELLOW } else { Color::WHITE };
+            let line = format!("\n{:>2}. {:<width$} {:>7}", rank + 1, entry.name, entry.score, width = leaderboard::MAX_NAME_LEN);
+            table.add(TextFragment::new(line).color(color));
+        }
+        table.set_scale(TEXT_SIZE * unit);
+        table.set_layout(graphics::TextLayout { h_align: graphics::TextAlign::Middle, v_align: graphics::TextAlign::Begin });
+        canvas.draw(&table, DrawParam::default().dest([x, y]));
+    }
+
+    // Overlay a block with its piece type's pattern when patterns are on
+    fn draw_pattern(&self, canvas: &mut graphics::Canvas, block_type: i32, x: f32, y: f32) {
+        if !self.settings.patterns {
+            return;
+        }
+        let pattern = &self.pattern_meshes[block_type as usize % self.pattern_meshes.len()];
+        if let Some(mesh) = pattern {
+            canvas.draw(mesh, DrawParam::default().dest([x * self.block_size, y * self.block_size]));
+        }
+    }
+
+    // Mark a block with its piece's letter, centered in the cell, when labels are on
+    fn draw_label(&self, canvas: &mut graphics::Canvas, name: &str, x: f32, y: f32) {
+        if !self.settings.labels {
+            return;
+        }
+        let mut label = Text::new(name);
+        label.set_scale(TEXT_SIZE * 0.75 * self.unit());
+        label.set_layout(graphics::TextLayout::center());
+        let center = [(x + 0.5) * self.block_size, (y + 0.5) * self.block_size];
+        canvas.draw(&label, DrawParam::default().dest(center).color(Color::new(0.0, 0.0, 0.0, 0.8)));
+    }
+
+    // Draw the settings overlay, marking the selected line
+    fn draw_settings(&self, canvas: &mut graphics::Canvas, game: &GameState, field_width: f32, field_height: f32) {
+        let unit = self.unit();
+        let marker = |row: usize| if self.settings_row == row { ">" } else { " " };
+        let settings_text = scaled_text(unit, format!(
+            "Settings\n\n{} Start level: {}\n{} Drop interval: {} ms\n{} Soft drop: {} cells/s\n{} Soft drop lock: {}\n{} Screen shake: {}\n{} Patterns: {}\n{} Lock resets: {}\n{} Cell highlight: {}\n{} Music volume: {}%\n\nLeft/Right: select\nUp/Down: change\nTab: close",
+            marker(0),
+            game.start_level(),
+            marker(1),
+            game.base_drop_interval().as_millis(),
+            marker(2),
+            game.soft_drop_speed(),
+            marker(3),
+            game.soft_drop_lock().name(),
+            marker(4),
+            if self.settings.shake { "on" } else { "off" },
+            marker(5),
+            if self.settings.patterns { "on" } else { "off" },
+            marker(6),
+            game.lock_reset_limit(),
+            marker(7),
+            if self.settings.cell_highlight { "on" } else { "off" },
+            marker(8),
+            self.music.volume()
+        ));
+        canvas.draw(
+            &settings_text,
+            DrawParam::default()
+                .dest([field_width / 2.0 - 90.0 * unit, field_height / 2.0 - 60.0 * unit])
+                .color(Color::WHITE),
+        );
+    }
+
+    // Draw the controls help as a translucent panel over the whole window, listing the
+    // current key bindings
+    fn draw_help(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let unit = self.unit();
+        let (width, height) = ctx.gfx.drawable_size();
+        let (offset_x, offset_y) = self.offset;
+        let panel = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(-offset_x, -offset_y, width, height),
+            Color::new(0.0, 0.0, 0.0, 0.8),
+        )?;
+        canvas.draw(&panel, DrawParam::default());
+
+        let mut lines = vec!["Controls".to_string(), String::new()];
+        for (key, action) in self.settings.keys.pairs() {
+            lines.push(format!("{}: {}", keys::describe(action), keys::key_name(key)));
+        }
+        let fixed = [
+            "",
+            "Tab: settings (paused)",
+            "M: mute music",
+            "F2: piece letter labels",
+            "F3: debug overlay",
+            "F5: save game",
+            "F11: fullscreen",
+            "Mouse: steer, click to drop",
+            "Controller: d-pad/stick move, A/B rotate,",
+            "  Y drop, bumpers hold, Start pause",
+            "Escape: quit (asks first during a game)",
+        ];
+        lines.extend(fixed.iter().map(|line| line.to_string()));
+        // The number keys pick pieces only in practice mode, up to the ninth of the set
+        if let Some(game) = self.game.as_ref().filter(|game| game.practice) {
+            let count = game.spawn_counts.len().min(9);
+            let names: Vec<&str> = (0..count as i32).map(|block_type| game.piece_name(block_type)).collect();
+            lines.push(format!("1-{}: pick next piece ({})", count, names.join(" ")));
+        }
+        if self.debug_keys {
+            lines.push("F6/F7/F8: game speed 0.25x/1x/4x".to_string());
+        }
+        lines.extend(["".to_string(), "H: close help".to_string()]);
+        canvas.draw(
+            &scaled_text(unit, lines.join("\n")),
+            DrawParam::default()
+                .dest([10.0 * unit, 10.0 * unit])
+                .color(Color::WHITE),
+        );
         Ok(())
     }
 }
 
+// Draw the title screen centered in a layout of the given size, with offsets and text scaled
+// by unit
+fn draw_menu(canvas: &mut graphics::Canvas, (width, height): (f32, f32), unit: f32, difficulty: Difficulty) {
+    let title_text = scaled_text(unit, "TETRIS");
+    canvas.draw(
+        &title_text,
+        DrawParam::default()
+            .dest([width / 2.0 - 25.0 * unit, height / 2.0 - 60.0 * unit])
+            .color(Color::CYAN),
+    );
+    let start_text = scaled_text(unit, format!(
+        "Press Enter to Start\nPress L to load saved game\nPress H for controls\nDifficulty: < {} >",
+        difficulty.label()
+    ));
+    canvas.draw(
+        &start_text,
+        DrawParam::default()
+            .dest([width / 2.0 - 80.0 * unit, height / 2.0 - 20.0 * unit])
+            .color(Color::WHITE),
+    );
+}
+
+// Itemize a line clear's points: the total, then the table points times the level and any bonuses
+fn describe_clear(clear: &ClearScore) -> String {
+    let name = match (clear.tspin, clear.lines) {
+        (true, 1) => "T-Spin Single",
+        (true, 2) => "T-Spin Double",
+        (true, _) => "T-Spin Triple",
+        (false, 1) => "Single",
+        (false, 2) => "Double",
+        (false, 3) => "Triple",
+        (false, _) => "Tetris",
+    };
+    let mut text = format!("+{}\n{}: {} x {}", clear.total(), name, clear.base, clear.level);
+    if clear.back_to_back > 0 {
+        text += &format!("\nB2B: +{}", clear.back_to_back);
+    }
+    if clear.combo > 0 {
+        text += &format!("\nCombo: +{}", clear.combo);
+    }
+    if clear.perfect_clear > 0 {
+        text += &format!("\nAll Clear: +{}", clear.perfect_clear);
+    }
+    text
+}
+
+// A full-size darker square under a 1px-inset fill gives each block a border once the mesh
+// is tinted with the block color, so same-colored neighbours stay distinct
+fn build_block_mesh(ctx: &Context, block_size: f32) -> GameResult<graphics::Mesh> {
+    let mut mesh = graphics::MeshBuilder::new();
+    mesh.rectangle(
+        graphics::DrawMode::fill(),
+        graphics::Rect::new(0.0, 0.0, block_size, block_size),
+        Color::new(0.5, 0.5, 0.5, 1.0),
+    )?
+    .rectangle(
+        graphics::DrawMode::fill(),
+        graphics::Rect::new(1.0, 1.0, block_size - 2.0, block_size - 2.0),
+        Color::WHITE,
+    )?;
+    Ok(graphics::Mesh::from_data(ctx, mesh.build()))
+}
+
+// Dark overlays that tell piece types apart without color, one per type in piece set order
+// (repeating for larger sets): plain, horizontal stripes, vertical stripes, dots, cross-hatch,
+// diagonal stripes and an inner square
+fn build_pattern_meshes(ctx: &Context, block_size: f32) -> GameResult<Vec<Option<graphics::Mesh>>> {
+    let shade = Color::new(0.0, 0.0, 0.0, 0.4);
+    let (size, line) = (block_size, (block_size / 12.0).max(1.0));
+    let (low, high) = (size * 0.2, size * 0.8);
+    let mut meshes = vec![None];
+    for pattern in 1..7 {
+        let mut mesh = graphics::MeshBuilder::new();
+        match pattern {
+            1 => {
+                for y in [0.25, 0.5, 0.75] {
+                    mesh.line(&[[low, size * y], [high, size * y]], line, shade)?;
+                }
+            }
+            2 => {
+                for x in [0.25, 0.5, 0.75] {
+                    mesh.line(&[[size * x, low], [size * x, high]], line, shade)?;
+                }
+            }
+            3 => {
+                for (x, y) in [(0.3, 0.3), (0.7, 0.3), (0.3, 0.7), (0.7, 0.7)] {
+                    mesh.circle(graphics::DrawMode::fill(), [size * x, size * y], size * 0.1, 0.5, shade)?;
+                }
+            }
+            4 => {
+                mesh.line(&[[low, low], [high, high]], line, shade)?;
+                mesh.line(&[[high, low], [low, high]], line, shade)?;
+            }
+            5 => {
+                mesh.line(&[[low, size * 0.5], [size * 0.5, low]], line, shade)?;
+                mesh.line(&[[low, high], [high, low]], line, shade)?;
+                mesh.line(&[[size * 0.5, high], [high, size * 0.5]], line, shade)?;
+            }
+            _ => {
+                let inner = graphics::Rect::new(size * 0.3, size * 0.3, size * 0.4, size * 0.4);
+                mesh.rectangle(graphics::DrawMode::stroke(line), inner, shade)?;
+            }
+        }
+        meshes.push(Some(graphics::Mesh::from_data(ctx, mesh.build())));
+    }
+    Ok(meshes)
+}
+
+// Width in cells of the playfield plus the side panel's preview, score and piece count columns
+fn layout_columns(grid_width: i32, preview_width: i32) -> i32 {
+    grid_width + preview_width + HUD_COLUMNS + COUNT_COLUMNS
+}
+
+// Where everything in the side panel goes: a column for the next queue and hold box beside
+// the playfield, then a column of score, level, lines and the other readouts, then the count
+// of each piece type dealt
+struct HudLayout {
+    preview_x: f32,    // Left edge of the next and hold column, --preview-width cells wide
+    stats_x: f32,      // Left edge of the score column
+    stats_width: f32,  // Width of the score column, HUD_COLUMNS cells
+    counts_x: f32,     // Left edge of the piece count column
+    field_height: f32, // Height of the visible playfield, which the columns stay within
+    block_size: f32,
+    unit: f32,
+}
+
+impl HudLayout {
+    fn new(field_width: f32, field_height: f32, preview_width: i32, block_size: f32, unit: f32) -> Self {
+        let stats_x = field_width + preview_width as f32 * block_size;
+        let stats_width = HUD_COLUMNS as f32 * block_size;
+        HudLayout {
+            preview_x: field_width,
+            stats_x,
+            stats_width,
+            counts_x: stats_x + stats_width,
+            field_height,
+            block_size,
+            unit,
+        }
+    }
+
+    // Top-left of the "Next:" label
+    fn next_label(&self) -> [f32; 2] {
+        [self.preview_x + HUD_MARGIN * self.unit, HUD_MARGIN * self.unit]
+    }
+
+    // The slot for the i-th queued piece, 4 by 2 cells
+    fn next_slot(&self, i: usize) -> graphics::Rect {
+        let y = (HUD_MARGIN + HUD_LINE) * self.unit + PREVIEW_SPACING * self.block_size * i as f32;
+        graphics::Rect::new(self.preview_x + HUD_MARGIN * self.unit, y, self.block_size * 4.0, self.block_size * 2.0)
+    }
+
+    // Top-left of the "Hold:" label, below the last queued piece
+    fn hold_label(&self) -> [f32; 2] {
+        let slot = self.next_slot(NEXT_PIECES - 1);
+        [slot.x, slot.y + slot.h + HUD_MARGIN * self.unit]
+    }
+
+    // The box around the held piece, with a small border around its 4 by 2 cells
+    fn hold_box(&self) -> graphics::Rect {
+        let [x, y] = self.hold_label();
+        let border = 5.0 * self.unit;
+        graphics::Rect::new(x - border, y + HUD_LINE * self.unit, self.block_size * 4.0 + 2.0 * border, self.block_size * 2.0 + 2.0 * border)
+    }
+
+    // Top-left of a line of text in the score column
+    fn stats_line(&self, line: usize) -> [f32; 2] {
+        [self.stats_x + HUD_MARGIN * self.unit, (HUD_MARGIN + HUD_LINE * line as f32) * self.unit]
+    }
+
+    // The level progress bar, filling a line of the score column
+    fn level_bar(&self, line: usize) -> graphics::Rect {
+        let [x, y] = self.stats_line(line);
+        graphics::Rect::new(x, y + 4.0 * self.unit, self.stats_width - 2.0 * HUD_MARGIN * self.unit, 8.0 * self.unit)
+    }
+
+    // Top-left of the "Pieces:" label over the piece counts
+    fn counts_label(&self) -> [f32; 2] {
+        [self.counts_x + HUD_MARGIN * self.unit, HUD_MARGIN * self.unit]
+    }
+
+    // The miniature slot for the i-th of types piece counts, its number drawn to the right;
+    // rows close up so a large piece set still ends above the bottom of the field
+    fn count_slot(&self, i: usize, types: usize) -> graphics::Rect {
+        let top = (HUD_MARGIN + HUD_LINE) * self.unit;
+        let room = (self.field_height - top - HUD_MARGIN * self.unit) / types.max(1) as f32;
+        let spacing = (COUNT_SPACING * self.unit).min(room);
+        graphics::Rect::new(self.counts_x + HUD_MARGIN * self.unit, top + spacing * i as f32, 30.0 * self.unit, 12.0 * self.unit)
+    }
+}
+
+// Text sized to match the block size
+fn scaled_text(unit: f32, contents: impl Into<TextFragment>) -> Text {
+    let mut text = Text::new(contents);
+    text.set_scale(TEXT_SIZE * unit);
+    text
+}
+
+// Blend a color halfway towards white
+fn brighten(color: Color) -> Color {
+    Color::new(
+        color.r + (1.0 - color.r) * 0.5,
+        color.g + (1.0 - color.g) * 0.5,
+        color.b + (1.0 - color.b) * 0.5,
+        color.a,
+    )
+}
+
+// Draw a single block with its top-left corner at the given pixel position
+fn draw_block(canvas: &mut graphics::Canvas, mesh: &graphics::Mesh, x: f32, y: f32, color: Color) {
+    canvas.draw(mesh, DrawParam::default().dest([x, y]).color(color));
+}
+
+// Draw a piece shape centered in an area, with cells up to max_cell pixels but smaller if the
+// shape would not fit, scaling the block mesh built for block_size
+fn draw_mini_piece(
+    canvas: &mut graphics::Canvas,
+    mesh: &graphics::Mesh,
+    block_size: f32,
+    (cells, color): (&[(i32, i32)], game::Color),
+    area: graphics::Rect,
+    max_cell: f32,
+) {
+    let columns = cells.iter().map(|&(x, _)| x + 1).max().unwrap_or(0) as f32;
+    let rows = cells.iter().map(|&(_, y)| y + 1).max().unwrap_or(0) as f32;
+    if columns == 0.0 {
+        return;
+    }
+    let cell = max_cell.min(area.w / columns).min(area.h / rows);
+    let x = area.x + (area.w - columns * cell) / 2.0;
+    let y = area.y + (area.h - rows * cell) / 2.0;
+    let scale = cell / block_size;
+    for &(cell_x, cell_y) in cells {
+        let dest = [x + cell_x as f32 * cell, y + cell_y as f32 * cell];
+        canvas.draw(mesh, DrawParam::default().dest(dest).scale([scale, scale]).color(color));
+    }
+}
+
+// The piece type a number key picks in practice mode: 1 for the first piece up to 9
+fn piece_key(key: KeyCode) -> Option<i32> {
+    const DIGITS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    DIGITS.iter().position(|&digit| digit == key).map(|index| index as i32)
+}
+
 // Main function to set up and run the game
 fn main() -> GameResult {
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            std::process::exit(2);
+        }
+    };
+
+    let replay = match options.replay.as_deref().map(Replay::load).transpose() {
+        Ok(replay) => replay,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            std::process::exit(2);
+        }
+    };
+
+    // Settings saved from the in-game overlay seed every new game
+    let settings = Config::load(config::CONFIG_FILE);
+    let mut config = options.game;
+    config.start_level = settings.start_level;
+    config.drop_interval = settings.drop_interval;
+    config.soft_drop_speed = settings.soft_drop_speed;
+    config.soft_drop_lock = settings.soft_drop_lock;
+    config.lock_reset_limit = settings.lock_resets;
+    if let Some(gravity) = &settings.gravity {
+        config.gravity = gravity.clone();
+    }
+
+    // Size the window to the chosen grid plus the side panel, or to the grid a replay was
+    // recorded on
+    let block_size = options.block_size as f32;
+    let (grid_width, grid_height) = replay.as_ref().map_or((config.width, config.height), |replay| (replay.config.width, replay.config.height));
+    let screen_width = layout_columns(grid_width, options.preview_width) as f32 * block_size;
+    let screen_height = grid_height as f32 * block_size;
+    let resources = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
     let cb = ggez::ContextBuilder::new("tetris", "cascade")
+        .add_resource_path(resources)
         .window_setup(ggez::conf::WindowSetup::default().title("Tetris"))
-        .window_mode(ggez::conf::WindowMode::default().dimensions(SCREEN_WIDTH, SCREEN_HEIGHT));
-    
-    let (ctx, event_loop) = cb.build()?;
-    let state = GameState::new();
+        .window_mode(ggez::conf::WindowMode::default().dimensions(screen_width, screen_height).resizable(true));
+    let fullscreen = settings.fullscreen;
+
+    let (mut ctx, event_loop) = cb.build()?;
+    let mut state = MainState::new(&mut ctx, config, settings, block_size, options.preview_width, replay, options.demo)?;
+    state.debug_keys = options.debug;
+    // A tall or wide custom grid can open a window bigger than the screen; shrink it to fit, and
+    // the resize rescales the board to match
+    if let Some(monitor) = ctx.gfx.window().current_monitor() {
+        let screen = monitor.size().to_logical::<f32>(monitor.scale_factor());
+        let scale = (screen.width * SCREEN_FILL / screen_width).min(screen.height * SCREEN_FILL / screen_height);
+        if scale < 1.0 {
+            ctx.gfx.set_drawable_size(screen_width * scale, screen_height * scale)?;
+            let (width, height) = ctx.gfx.drawable_size();
+            event::EventHandler::resize_event(&mut state, &mut ctx, width, height)?;
+        }
+    }
+    // Enter a remembered fullscreen mode after the window opens, so leaving it returns to the
+    // normal window size
+    if fullscreen {
+        ctx.gfx.set_fullscreen(FullscreenType::Desktop)?;
+        let (width, height) = ctx.gfx.drawable_size();
+        event::EventHandler::resize_event(&mut state, &mut ctx, width, height)?;
+    }
     event::run(ctx, event_loop, state)
-}
\ No newline at end of file
+}
@@ -0,0 +1,137 @@
+// Piece definitions: the standard seven tetrominoes, or an alternate set loaded from a file
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+
+use crate::game::{Color, Theme};
+
+const MAX_PIECE_SIZE: i32 = 5; // Widest and tallest piece a set may define, to fit the preview
+
+// Which wall kick table a piece's rotations use
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Kicks {
+    None,     // The piece never rotates
+    Standard, // SRS kicks shared by J, L, S, T and Z, also used for custom pieces
+    Long,     // SRS kicks for the I piece
+}
+
+// The shape and color of one kind of piece, in its spawn orientation
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PieceDef {
+    pub name: String,           // Short name, such as "T"
+    pub cells: Vec<(i32, i32)>, // Cell offsets from the top-left of the bounding box, y down
+    pub color: Color,
+    pub pivot: (i32, i32),      // Rotation center in half-cell units, relative to the offsets
+    pub kicks: Kicks,
+    pub spawn: (i32, i32),      // Shift from the default centered spawn position
+}
+
+impl PieceDef {
+    // Build a custom piece, normalizing its cells to the top-left corner and turning it
+    // around the center of its bounding square
+    fn custom(name: &str, cells: &[(i32, i32)], color: Color) -> PieceDef {
+        let min_x = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+        let min_y = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+        let cells: Vec<(i32, i32)> = cells.iter().map(|&(x, y)| (x - min_x, y - min_y)).collect();
+        let size = cells.iter().map(|&(x, y)| x.max(y) + 1).max().unwrap_or(1);
+        PieceDef { name: name.to_string(), cells, color, pivot: (size - 1, size - 1), kicks: Kicks::Standard, spawn: (0, 0) }
+    }
+
+    // Width of the piece's spawn orientation in cells
+    pub fn width(&self) -> i32 {
+        self.cells.iter().map(|&(x, _)| x + 1).max().unwrap_or(0)
+    }
+}
+
+// The seven standard tetrominoes (I, O, L, J, T, S, Z), colored from a theme. Pivots are the
+// centers of their SRS bounding boxes: the I and O pieces turn around a grid corner, the
+// others around their middle cell.
+pub fn standard(theme: Theme) -> Vec<PieceDef> {
+    let [i, o, l, j, t, s, z] = theme.palette();
+    let piece = |name: &str, cells: [(i32, i32); 4], color, pivot, kicks| PieceDef {
+        name: name.to_string(),
+        cells: cells.to_vec(),
+        color,
+        pivot,
+        kicks,
+        spawn: (0, 0),
+    };
+    vec![
+        piece("I", [(0, 0), (1, 0), (2, 0), (3, 0)], i, (3, 1), Kicks::Long),
+        piece("O", [(0, 0), (1, 0), (0, 1), (1, 1)], o, (1, 1), Kicks::None),
+        piece("L", [(0, 0), (0, 1), (1, 1), (2, 1)], l, (2, 2), Kicks::Standard),
+        piece("J", [(2, 0), (0, 1), (1, 1), (2, 1)], j, (2, 2), Kicks::Standard),
+        piece("T", [(1, 0), (0, 1), (1, 1), (2, 1)], t, (2, 2), Kicks::Standard),
+        piece("S", [(1, 0), (2, 0), (0, 1), (1, 1)], s, (2, 2), Kicks::Standard),
+        piece("Z", [(0, 0), (1, 0), (1, 1), (2, 1)], z, (2, 2), Kicks::Standard),
+    ]
+}
+
+// Load a piece set from a file with one piece per line: a name, a #rrggbb color, then the
+// piece's cells as x,y pairs, optionally followed by spawn=dx,dy to shift where it spawns.
+// Blank lines and lines starting with '#' are ignored.
+pub fn load(path: &str) -> Result<Vec<PieceDef>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    let mut pieces = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let piece = parse_piece(line).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+        pieces.push(piece);
+    }
+    if pieces.is_empty() {
+        return Err(format!("{} defines no pieces", path));
+    }
+    Ok(pieces)
+}
+
+// Parse one piece definition line
+fn parse_piece(line: &str) -> Result<PieceDef, String> {
+    let mut fields = line.split_whitespace();
+    let name = fields.next().ok_or("missing piece name")?;
+    let color = parse_color(fields.next().ok_or("missing piece color")?)?;
+
+    let mut cells = Vec::new();
+    let mut seen = HashSet::new();
+    let mut spawn = (0, 0);
+    for field in fields {
+        if let Some(offset) = field.strip_prefix("spawn=") {
+            spawn = parse_pair(offset).ok_or_else(|| format!("expected a spawn offset as spawn=dx,dy, got '{}'", field))?;
+            continue;
+        }
+        let cell = parse_pair(field).ok_or_else(|| format!("expected a cell as x,y, got '{}'", field))?;
+        if !seen.insert(cell) {
+            return Err(format!("cell {} is listed twice", field));
+        }
+        cells.push(cell);
+    }
+    if cells.is_empty() {
+        return Err(format!("piece '{}' has no cells", name));
+    }
+
+    let piece = PieceDef { spawn, ..PieceDef::custom(name, &cells, color) };
+    let height = piece.cells.iter().map(|&(_, y)| y + 1).max().unwrap_or(0);
+    if piece.width() > MAX_PIECE_SIZE || height > MAX_PIECE_SIZE {
+        return Err(format!("piece '{}' must fit in {} by {} cells", name, MAX_PIECE_SIZE, MAX_PIECE_SIZE));
+    }
+    Ok(piece)
+}
+
+// Parse a pair of whole numbers written as x,y
+fn parse_pair(value: &str) -> Option<(i32, i32)> {
+    let (x, y) = value.split_once(',')?;
+    Some((x.parse().ok()?, y.parse().ok()?))
+}
+
+// Parse a #rrggbb color
+fn parse_color(value: &str) -> Result<Color, String> {
+    let hex = value.strip_prefix('#').filter(|hex| hex.len() == 6);
+    let channels = hex.and_then(|hex| {
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some((channel(0)?, channel(2)?, channel(4)?))
+    });
+    let (r, g, b) = channels.ok_or_else(|| format!("expected a color as #rrggbb, got '{}'", value))?;
+    Ok(Color::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0))
+}
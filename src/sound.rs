@@ -0,0 +1,120 @@
+// Sound effects for game events, loaded from the resources directory
+use ggez::audio::{self, SoundSource};
+use ggez::Context;
+
+use crate::game::GameEvent;
+
+// Optional effects: any file that is missing or fails to decode is simply not played
+pub struct Sounds {
+    lock: Option<audio::Source>,
+    clear: Option<audio::Source>,
+    rotate: Option<audio::Source>,
+    game_over: Option<audio::Source>,
+}
+
+impl Sounds {
+    pub fn load(ctx: &mut Context) -> Self {
+        Sounds {
+            lock: load_source(ctx, "/sounds/lock.ogg"),
+            clear: load_source(ctx, "/sounds/clear.ogg"),
+            rotate: load_source(ctx, "/sounds/rotate.ogg"),
+            game_over: load_source(ctx, "/sounds/game_over.ogg"),
+        }
+    }
+
+    // Play the effect for an event, if one is loaded
+    pub fn play(&mut self, ctx: &mut Context, event: GameEvent) {
+        let (source, pitch) = match event {
+            GameEvent::PieceLocked => (&mut self.lock, 1.0),
+            // Bigger clears play higher, up to half again the pitch for a tetris
+            GameEvent::LinesCleared(lines) => (&mut self.clear, 1.0 + (lines - 1) as f32 / 6.0),
+            GameEvent::Rotated => (&mut self.rotate, 1.0),
+            GameEvent::GameOver => (&mut self.game_over, 1.0),
+            GameEvent::TSpin(_) | GameEvent::PerfectClear | GameEvent::StackReset | GameEvent::LevelUp(_) => return,
+        };
+        if let Some(source) = source {
+            source.set_pitch(pitch);
+            if let Err(e) = source.play_detached(ctx) {
+                eprintln!("Failed to play sound: {}", e);
+            }
+        }
+    }
+}
+
+fn load_source(ctx: &mut Context, path: &str) -> Option<audio::Source> {
+    audio::Source::new(ctx, path).ok()
+}
+
+pub const MUSIC_VOLUME: u32 = 50; // Default background music level, in percent of the effects
+
+// Optional looping background music; a missing file leaves the game silent
+pub struct Music {
+    source: Option<audio::Source>,
+    volume: u32, // Level in percent of the effects, kept while muted
+    muted: bool,
+    playing: bool,
+}
+
+impl Music {
+    pub fn load(ctx: &mut Context, muted: bool, volume: u32) -> Self {
+        let mut source = load_source(ctx, "/sounds/music.ogg");
+        if let Some(source) = &mut source {
+            source.set_repeat(true);
+        }
+        let mut music = Music { source, volume, muted, playing: false };
+        music.apply_volume();
+        music
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    // Mute or unmute without interrupting playback
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        self.apply_volume();
+    }
+
+    pub fn volume(&self) -> u32 {
+        self.volume
+    }
+
+    // Change the level without interrupting playback; a muted track stays silent
+    pub fn set_volume(&mut self, volume: u32) {
+        self.volume = volume;
+        self.apply_volume();
+    }
+
+    fn apply_volume(&mut self) {
+        let volume = if self.muted { 0.0 } else { self.volume as f32 / 100.0 };
+        if let Some(source) = &mut self.source {
+            source.set_volume(volume);
+        }
+    }
+
+    // Start the music from the beginning unless it is already playing
+    pub fn start(&mut self, ctx: &mut Context) {
+        if self.playing {
+            return;
+        }
+        if let Some(source) = &mut self.source {
+            if let Err(e) = source.play(ctx) {
+                eprintln!("Failed to play music: {}", e);
+            }
+        }
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self, ctx: &mut Context) {
+        if !self.playing {
+            return;
+        }
+        if let Some(source) = &mut self.source {
+            if let Err(e) = source.stop(ctx) {
+                eprintln!("Failed to stop music: {}", e);
+            }
+        }
+        self.playing = false;
+    }
+}